@@ -0,0 +1,322 @@
+use gstreamer as gst;
+
+use crate::container::{Container, MuxerConfig};
+use crate::corruption::CorruptionPolicy;
+use crate::encoder::{Encoder, EncoderConfig, RateControl};
+use crate::error::InvalidSetting;
+use crate::modes::{ModeConstraints, PixelFormat};
+use crate::naming::LocationTemplate;
+use crate::retention::RetentionPolicy;
+use crate::slate::SlateConfig;
+use crate::source::{self, VideoSource};
+use crate::storage::StoragePolicy;
+use crate::supervisor::RestartPolicy;
+use crate::watchdog::WatchdogConfig;
+
+/// Everything needed to build a recording pipeline.
+///
+/// Use [`RecorderConfig::builder`] to get the defaults the recorder has
+/// always used and only override what differs.
+#[derive(Debug, Clone)]
+pub struct RecorderConfig {
+    /// Camera name, used to tell several recorders apart.
+    pub name: String,
+    pub source: VideoSource,
+    /// Camera mode requirements, the best matching mode is picked when the
+    /// recorder starts.
+    pub mode: ModeConstraints,
+    pub encoder: EncoderConfig,
+    /// Record the camera's MJPEG frames as they are instead of encoding,
+    /// `encoder` is ignored then.
+    pub passthrough: bool,
+    // sink
+    /// printf-style pattern such as `video%05d.mp4`, or a template, see
+    /// [`crate::naming`].
+    pub location: String,
+    pub max_size_time: gst::ClockTime,
+    /// Split at wall-clock multiples of `max_size_time` counted from local
+    /// midnight, e.g. every full minute for 60 s, rather than every
+    /// `max_size_time` from the start.
+    pub align_splits: bool,
+    pub muxer: MuxerConfig,
+    /// How long stopping waits for EOS to finalize the last segment.
+    pub shutdown_timeout: gst::ClockTime,
+    /// Which old segments get deleted, on start and after every segment.
+    pub retention: RetentionPolicy,
+    /// What happens when the output filesystem runs out of space.
+    pub storage: StoragePolicy,
+    /// Whether and how the pipeline gets rebuilt after errors.
+    pub restart: RestartPolicy,
+    /// What is recorded while the source sends nothing.
+    pub slate: SlateConfig,
+    /// What happens when the source stops sending while playing.
+    pub watchdog: WatchdogConfig,
+    /// How many corrupt MJPEG frames are tolerated.
+    pub corruption: CorruptionPolicy,
+}
+
+impl RecorderConfig {
+    pub fn builder<S: Into<VideoSource>, L: Into<String>>(source: S, location: L) -> RecorderConfigBuilder {
+        RecorderConfigBuilder {
+            config: RecorderConfig {
+                name: String::from("camera"),
+                source: source.into(),
+                mode: ModeConstraints {
+                    prefer_format: Some(PixelFormat::Mjpeg),
+                    ..ModeConstraints::default()
+                },
+                encoder: EncoderConfig::default(),
+                passthrough: false,
+                location: location.into(),
+                max_size_time: gst::ClockTime::from_seconds(10),
+                align_splits: false,
+                muxer: MuxerConfig::default(),
+                shutdown_timeout: gst::ClockTime::from_seconds(5),
+                retention: RetentionPolicy::default(),
+                storage: StoragePolicy::default(),
+                restart: RestartPolicy::default(),
+                slate: SlateConfig::default(),
+                watchdog: WatchdogConfig::default(),
+                corruption: CorruptionPolicy::default(),
+            },
+        }
+    }
+
+    /// Checks every value, naming the offending settings key on failure.
+    pub fn validate(&self) -> Result<(), InvalidSetting> {
+        self.validate_capture()?;
+        if self.location.is_empty() {
+            return Err(InvalidSetting::new("output.location", "must not be empty"));
+        }
+        LocationTemplate::from_location(&self.location)?;
+        if self.max_size_time.nseconds().unwrap_or(0) == 0 {
+            return Err(InvalidSetting::new("output.segment_duration", "must be greater than 0"));
+        }
+        let segment_seconds = self.max_size_time.seconds().unwrap_or(0);
+        if self.align_splits && (segment_seconds == 0 || 86_400 % segment_seconds != 0) {
+            return Err(InvalidSetting::new(
+                "output.segment_duration",
+                "must be whole seconds dividing a day to align splits, e.g. 60 or 3600",
+            ));
+        }
+        self.muxer.validate(&self.location, self.stream_format())?;
+        if self.shutdown_timeout.nseconds().unwrap_or(0) == 0 {
+            return Err(InvalidSetting::new("output.shutdown_timeout", "must be greater than 0"));
+        }
+        Ok(())
+    }
+
+    /// Like [`RecorderConfig::validate`] but leaves out the output
+    /// settings, for capturing without recording segments.
+    pub fn validate_capture(&self) -> Result<(), InvalidSetting> {
+        match &self.source {
+            VideoSource::V4l2 { device } if device.is_empty() => {
+                return Err(InvalidSetting::new("source.device", "must not be empty"));
+            }
+            VideoSource::Test { pattern } => source::validate_test_pattern("source.pattern", pattern)?,
+            VideoSource::File { path } if path.as_os_str().is_empty() => {
+                return Err(InvalidSetting::new("source.path", "must not be empty"));
+            }
+            VideoSource::Uri { uri } if !uri.contains("://") => {
+                return Err(InvalidSetting::new("source.uri", format!("{} is not an URI", uri)));
+            }
+            _ => (),
+        }
+        let mode_values = [
+            ("video.width", self.mode.width),
+            ("video.height", self.mode.height),
+            ("video.framerate", self.mode.framerate),
+            ("video.max_width", self.mode.max_width),
+            ("video.max_height", self.mode.max_height),
+            ("video.min_framerate", self.mode.min_framerate),
+        ];
+        for (key, value) in mode_values.iter() {
+            if value.map(|v| v <= 0).unwrap_or(false) {
+                return Err(InvalidSetting::new(*key, "must be greater than 0"));
+            }
+        }
+        self.encoder.validate()?;
+        if self.passthrough {
+            if !matches!(self.source, VideoSource::V4l2 { .. }) {
+                return Err(InvalidSetting::new("encoder.passthrough", "only supported for v4l2 sources"));
+            }
+            if let Some(PixelFormat::Raw(format)) = &self.mode.format {
+                return Err(InvalidSetting::new(
+                    "video.format",
+                    format!("passthrough records MJPEG, got {}", format),
+                ));
+            }
+        }
+        self.retention.validate()?;
+        self.storage.validate(self.stream_format() != Encoder::Mjpeg)?;
+        self.restart.validate()?;
+        self.slate.validate()?;
+        if self.slate.enabled && self.passthrough {
+            return Err(InvalidSetting::new("slate.enabled", "not supported with passthrough"));
+        }
+        self.watchdog.validate(self.restart.enabled)?;
+        self.corruption.validate()?;
+        Ok(())
+    }
+
+    /// Format of the recorded video stream, MJPEG for passthrough.
+    pub fn stream_format(&self) -> Encoder {
+        if self.passthrough {
+            Encoder::Mjpeg
+        } else {
+            self.encoder.kind
+        }
+    }
+
+    /// Container the segments are written in.
+    pub fn container(&self) -> Container {
+        self.muxer.container(&self.location, self.stream_format())
+    }
+}
+
+pub struct RecorderConfigBuilder {
+    config: RecorderConfig,
+}
+
+impl RecorderConfigBuilder {
+    pub fn name<N: Into<String>>(mut self, name: N) -> Self {
+        self.config.name = name.into();
+        self
+    }
+
+    pub fn resolution(mut self, width: i32, height: i32) -> Self {
+        self.config.mode.width = Some(width);
+        self.config.mode.height = Some(height);
+        self
+    }
+
+    pub fn framerate(mut self, framerate: i32) -> Self {
+        self.config.mode.framerate = Some(framerate);
+        self
+    }
+
+    pub fn max_resolution(mut self, width: i32, height: i32) -> Self {
+        self.config.mode.max_width = Some(width);
+        self.config.mode.max_height = Some(height);
+        self
+    }
+
+    pub fn min_framerate(mut self, framerate: i32) -> Self {
+        self.config.mode.min_framerate = Some(framerate);
+        self
+    }
+
+    pub fn prefer_format(mut self, format: PixelFormat) -> Self {
+        self.config.mode.prefer_format = Some(format);
+        self
+    }
+
+    pub fn encoder(mut self, encoder: Encoder) -> Self {
+        self.config.encoder.kind = encoder;
+        self
+    }
+
+    pub fn bitrate(mut self, bitrate: u32) -> Self {
+        self.config.encoder.bitrate = Some(bitrate);
+        self
+    }
+
+    pub fn rate_control(mut self, rate_control: RateControl) -> Self {
+        self.config.encoder.rate_control = Some(rate_control);
+        self
+    }
+
+    pub fn quantizer(mut self, quantizer: u32) -> Self {
+        self.config.encoder.quantizer = Some(quantizer);
+        self
+    }
+
+    pub fn speed_preset<P: Into<String>>(mut self, speed_preset: P) -> Self {
+        self.config.encoder.speed_preset = Some(speed_preset.into());
+        self
+    }
+
+    pub fn tune<T: Into<String>>(mut self, tune: T) -> Self {
+        self.config.encoder.tune = Some(tune.into());
+        self
+    }
+
+    pub fn key_int_max(mut self, key_int_max: u32) -> Self {
+        self.config.encoder.key_int_max = key_int_max;
+        self
+    }
+
+    pub fn profile<P: Into<String>>(mut self, profile: P) -> Self {
+        self.config.encoder.profile = Some(profile.into());
+        self
+    }
+
+    pub fn passthrough(mut self, passthrough: bool) -> Self {
+        self.config.passthrough = passthrough;
+        self
+    }
+
+    pub fn max_size_time(mut self, max_size_time: gst::ClockTime) -> Self {
+        self.config.max_size_time = max_size_time;
+        self
+    }
+
+    pub fn align_splits(mut self, align_splits: bool) -> Self {
+        self.config.align_splits = align_splits;
+        self
+    }
+
+    pub fn container(mut self, container: Container) -> Self {
+        self.config.muxer.container = Some(container);
+        self
+    }
+
+    pub fn fragment_duration(mut self, fragment_duration: gst::ClockTime) -> Self {
+        self.config.muxer.fragment_duration = Some(fragment_duration);
+        self
+    }
+
+    pub fn streamable(mut self, streamable: bool) -> Self {
+        self.config.muxer.streamable = Some(streamable);
+        self
+    }
+
+    pub fn shutdown_timeout(mut self, shutdown_timeout: gst::ClockTime) -> Self {
+        self.config.shutdown_timeout = shutdown_timeout;
+        self
+    }
+
+    pub fn retention(mut self, retention: RetentionPolicy) -> Self {
+        self.config.retention = retention;
+        self
+    }
+
+    pub fn storage(mut self, storage: StoragePolicy) -> Self {
+        self.config.storage = storage;
+        self
+    }
+
+    pub fn restart(mut self, restart: RestartPolicy) -> Self {
+        self.config.restart = restart;
+        self
+    }
+
+    pub fn slate(mut self, slate: SlateConfig) -> Self {
+        self.config.slate = slate;
+        self
+    }
+
+    pub fn watchdog(mut self, watchdog: WatchdogConfig) -> Self {
+        self.config.watchdog = watchdog;
+        self
+    }
+
+    pub fn corruption(mut self, corruption: CorruptionPolicy) -> Self {
+        self.config.corruption = corruption;
+        self
+    }
+
+    pub fn build(self) -> RecorderConfig {
+        self.config
+    }
+}
@@ -0,0 +1,236 @@
+use gstreamer as gst;
+use gst::prelude::*;
+
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::encoder::Encoder;
+use crate::error::{InvalidSetting, RecorderError};
+use crate::pipeline::make_element;
+
+/// Fragment duration of fragmented MP4 when none is configured.
+const DEFAULT_FRAGMENT_DURATION_MS: u64 = 1000;
+
+/// File format of the recorded segments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Container {
+    Mp4,
+    /// MP4 written as a series of fragments, playable up to the last
+    /// complete fragment when the recorder dies.
+    #[serde(alias = "fmp4")]
+    FragmentedMp4,
+    #[serde(alias = "mkv")]
+    Matroska,
+    #[serde(alias = "ts")]
+    MpegTs,
+    Avi,
+}
+
+impl Container {
+    pub fn muxer_name(self) -> &'static str {
+        match self {
+            Container::Mp4 | Container::FragmentedMp4 => "mp4mux",
+            Container::Matroska => "matroskamux",
+            Container::MpegTs => "mpegtsmux",
+            Container::Avi => "avimux",
+        }
+    }
+
+    /// File extensions segments of this container may have.
+    pub fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Container::Mp4 | Container::FragmentedMp4 => &["mp4", "m4v"],
+            Container::Matroska => &["mkv"],
+            Container::MpegTs => &["ts", "m2ts"],
+            Container::Avi => &["avi"],
+        }
+    }
+
+    /// Whether the muxer takes streams produced by `format`.
+    pub fn supports(self, format: Encoder) -> bool {
+        match self {
+            Container::Mp4 | Container::FragmentedMp4 => format != Encoder::Vp8,
+            Container::Matroska => true,
+            Container::MpegTs => format == Encoder::X264 || format == Encoder::X265,
+            Container::Avi => matches!(format, Encoder::X264 | Encoder::Vp8 | Encoder::Mjpeg),
+        }
+    }
+
+    /// Guesses the container from the extension of `location`.
+    pub fn from_location(location: &str) -> Option<Container> {
+        let extension = Path::new(location).extension()?.to_str()?.to_lowercase();
+        [Container::Mp4, Container::Matroska, Container::MpegTs, Container::Avi]
+            .iter()
+            .cloned()
+            .find(|container| container.extensions().contains(&extension.as_str()))
+    }
+}
+
+impl FromStr for Container {
+    type Err = RecorderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mp4" => Ok(Container::Mp4),
+            "fragmentedmp4" | "fmp4" => Ok(Container::FragmentedMp4),
+            "matroska" | "mkv" => Ok(Container::Matroska),
+            "mpegts" | "ts" => Ok(Container::MpegTs),
+            "avi" => Ok(Container::Avi),
+            _ => Err(RecorderError::UnknownValue { kind: "container", value: s.into() }),
+        }
+    }
+}
+
+impl fmt::Display for Container {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Container::Mp4 => "mp4",
+            Container::FragmentedMp4 => "fragmentedmp4",
+            Container::Matroska => "matroska",
+            Container::MpegTs => "mpegts",
+            Container::Avi => "avi",
+        })
+    }
+}
+
+/// Container and muxer options of the segments.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MuxerConfig {
+    /// Guessed from the location extension when unset.
+    pub container: Option<Container>,
+    /// Fragment duration of fragmented MP4.
+    pub fragment_duration: Option<gst::ClockTime>,
+    /// Write files that can be played while being recorded, for Matroska
+    /// and fragmented MP4.
+    pub streamable: Option<bool>,
+}
+
+impl MuxerConfig {
+    /// Container used for segments at `location` holding `format`: the
+    /// configured one, else the one matching the extension, else MP4 or,
+    /// for what MP4 can't hold, Matroska.
+    pub fn container(&self, location: &str, format: Encoder) -> Container {
+        self.container
+            .or_else(|| Container::from_location(location))
+            .unwrap_or_else(|| if Container::Mp4.supports(format) {
+                Container::Mp4
+            } else {
+                Container::Matroska
+            })
+    }
+
+    pub fn validate(&self, location: &str, format: Encoder) -> Result<(), InvalidSetting> {
+        let container = self.container(location, format);
+
+        if let Some(container) = self.container {
+            let matches = Path::new(location)
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .map(|extension| container.extensions().contains(&extension.to_lowercase().as_str()))
+                .unwrap_or(false);
+            if !matches {
+                return Err(InvalidSetting::new(
+                    "output.location",
+                    format!("{} segments need a .{} extension", container, container.extensions().join(" or .")),
+                ));
+            }
+        }
+        if !container.supports(format) {
+            return Err(InvalidSetting::new(
+                "output.container",
+                format!("{} cannot hold {} video", container, format),
+            ));
+        }
+        if let Some(fragment_duration) = self.fragment_duration {
+            if container != Container::FragmentedMp4 {
+                return Err(InvalidSetting::new(
+                    "output.fragment_duration",
+                    format!("only supported for fragmentedmp4, not {}", container),
+                ));
+            }
+            if fragment_duration.mseconds().unwrap_or(0) == 0 {
+                return Err(InvalidSetting::new("output.fragment_duration", "must be greater than 0"));
+            }
+        }
+        if self.streamable.is_some()
+            && container != Container::Matroska
+            && container != Container::FragmentedMp4
+        {
+            return Err(InvalidSetting::new(
+                "output.streamable",
+                format!("only supported for matroska and fragmentedmp4, not {}", container),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Builds the muxer to hand to splitmuxsink.
+    pub fn build(&self, location: &str, format: Encoder) -> Result<gst::Element, RecorderError> {
+        let container = self.container(location, format);
+        let muxer = make_element(container.muxer_name(), "muxer")?;
+
+        if container == Container::FragmentedMp4 {
+            let fragment_duration = self.fragment_duration
+                .and_then(|duration| duration.mseconds())
+                .unwrap_or(DEFAULT_FRAGMENT_DURATION_MS);
+            muxer.set_property("fragment-duration", &(fragment_duration as u32))?;
+        }
+        if let Some(streamable) = self.streamable {
+            muxer.set_property("streamable", &streamable)?;
+        }
+
+        Ok(muxer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testutil::rejected;
+
+    #[test]
+    fn guesses_the_container() {
+        let config = MuxerConfig::default();
+        assert_eq!(config.container("rec/%05d.MKV", Encoder::X264), Container::Matroska);
+        assert_eq!(config.container("rec/%05d", Encoder::X264), Container::Mp4);
+        assert_eq!(config.container("rec/%05d", Encoder::Vp8), Container::Matroska);
+        assert!(config.validate("rec/%05d.ts", Encoder::X265).is_ok());
+    }
+
+    #[test]
+    fn rejects_containers_not_holding_the_format() {
+        let config = MuxerConfig::default();
+        assert_eq!(rejected(config.validate("rec/%05d.mp4", Encoder::Vp8)).key, "output.container");
+        assert_eq!(rejected(config.validate("rec/%05d.ts", Encoder::Mjpeg)).key, "output.container");
+    }
+
+    #[test]
+    fn rejects_a_location_not_matching_the_container() {
+        let config = MuxerConfig { container: Some(Container::Matroska), ..MuxerConfig::default() };
+        assert_eq!(rejected(config.validate("rec/%05d.mp4", Encoder::X264)).key, "output.location");
+        assert!(config.validate("rec/%05d.mkv", Encoder::X264).is_ok());
+    }
+
+    #[test]
+    fn rejects_options_of_other_containers() {
+        let streamable = MuxerConfig { streamable: Some(true), ..MuxerConfig::default() };
+        assert_eq!(rejected(streamable.validate("rec/%05d.mp4", Encoder::X264)).key, "output.streamable");
+        assert!(streamable.validate("rec/%05d.mkv", Encoder::X264).is_ok());
+
+        let fragmented = MuxerConfig {
+            fragment_duration: Some(gst::ClockTime::from_seconds(1)),
+            ..MuxerConfig::default()
+        };
+        assert_eq!(rejected(fragmented.validate("rec/%05d.mp4", Encoder::X264)).key, "output.fragment_duration");
+        let fragmented = MuxerConfig { container: Some(Container::FragmentedMp4), ..fragmented };
+        assert!(fragmented.validate("rec/%05d.mp4", Encoder::X264).is_ok());
+        let empty = MuxerConfig { fragment_duration: Some(gst::ClockTime::from_mseconds(0)), ..fragmented };
+        assert_eq!(rejected(empty.validate("rec/%05d.mp4", Encoder::X264)).key, "output.fragment_duration");
+    }
+}
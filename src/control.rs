@@ -0,0 +1,159 @@
+//! Local HTTP/JSON API operating a recorder at runtime, served with
+//! [`crate::http::serve`] on TCP or a Unix socket.
+//!
+//! - `GET /status`: the [`RecorderStatus`] as JSON
+//! - `POST /start`, `/stop`, `/pause`, `/resume`, `/split`: act and answer
+//!   the new status
+//! - `GET /snapshot`: the next frame as JPEG
+//! - `PUT /bitrate` with `{"kbps": 2000}`: change the encoder bitrate
+//!
+//! Errors are answered as `{"error": "...", "kind": "state"}`.
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{ErrorKind, RecorderError};
+use crate::http::{Handler, Request, Response};
+use crate::recorder::{CameraRecorder, RecorderStatus};
+
+const JSON: &str = "application/json";
+
+const PATHS: &[&str] = &["/status", "/start", "/stop", "/pause", "/resume", "/split", "/snapshot", "/bitrate"];
+
+#[derive(Serialize)]
+struct Status<'a> {
+    camera: &'a str,
+    running: bool,
+    paused: bool,
+    state: Option<String>,
+    next_index: u32,
+    bitrate: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct Failure {
+    error: String,
+    kind: String,
+}
+
+#[derive(Deserialize)]
+struct Bitrate {
+    kbps: u32,
+}
+
+/// Answers the control requests for `recorder`.
+pub fn handler(recorder: Arc<CameraRecorder>) -> Handler {
+    Arc::new(move |request: &Request| {
+        let recorder = &*recorder;
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/status") => status(recorder),
+            ("POST", "/start") => act(recorder, CameraRecorder::start),
+            ("POST", "/stop") => act(recorder, CameraRecorder::stop),
+            ("POST", "/pause") => act(recorder, CameraRecorder::pause),
+            ("POST", "/resume") => act(recorder, CameraRecorder::resume),
+            ("POST", "/split") => act(recorder, CameraRecorder::split_now),
+            ("GET", "/snapshot") => match recorder.snapshot() {
+                Ok(jpeg) => Response::new(200, "image/jpeg", jpeg),
+                Err(err) => failure(&err),
+            },
+            ("PUT", "/bitrate") | ("POST", "/bitrate") => match serde_json::from_slice::<Bitrate>(&request.body) {
+                Ok(bitrate) => act(recorder, |recorder| recorder.set_bitrate(bitrate.kbps)),
+                Err(err) => json(400, &Failure { error: err.to_string(), kind: String::from("request") }),
+            },
+            (_, path) if PATHS.contains(&path) => Response::text(405, "Method not allowed\n"),
+            _ => Response::not_found(),
+        }
+    })
+}
+
+fn act<F>(recorder: &CameraRecorder, action: F) -> Response
+where
+    F: FnOnce(&CameraRecorder) -> Result<(), RecorderError>,
+{
+    match action(recorder) {
+        Ok(()) => status(recorder),
+        Err(err) => failure(&err),
+    }
+}
+
+fn status(recorder: &CameraRecorder) -> Response {
+    let RecorderStatus { running, paused, state, next_index, bitrate } = recorder.status();
+    json(200, &Status {
+        camera: &recorder.config().name,
+        running,
+        paused,
+        state: state.map(|s| format!("{:?}", s).to_lowercase()),
+        next_index,
+        bitrate,
+    })
+}
+
+fn failure(err: &RecorderError) -> Response {
+    let status = match err.kind() {
+        ErrorKind::Config => 400,
+        ErrorKind::State => 409,
+        _ => 500,
+    };
+    json(status, &Failure { error: err.to_string(), kind: format!("{:?}", err.kind()).to_lowercase() })
+}
+
+fn json<T: Serialize>(status: u16, body: &T) -> Response {
+    match serde_json::to_vec(body) {
+        Ok(body) => Response::new(status, JSON, body),
+        Err(err) => Response::text(500, format!("{}\n", err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::RecorderConfig;
+    use crate::source::VideoSource;
+    use crate::testutil::exchange;
+
+    fn stopped() -> Handler {
+        let source = VideoSource::V4l2 { device: String::from("/dev/video0") };
+        let config = RecorderConfig::builder(source, "video%05d.mkv").name("front").build();
+        handler(Arc::new(CameraRecorder::new(config)))
+    }
+
+    fn body(response: &str) -> &str {
+        response.split_once("\r\n\r\n").map(|(_, body)| body).unwrap_or("")
+    }
+
+    #[test]
+    fn answers_the_status() {
+        let response = exchange(&stopped(), "GET /status HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n"), "{}", response);
+        assert_eq!(
+            body(&response),
+            r#"{"camera":"front","running":false,"paused":false,"state":null,"next_index":0,"bitrate":null}"#,
+        );
+    }
+
+    #[test]
+    fn answers_errors_with_their_kind() {
+        let response = exchange(&stopped(), "POST /pause HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 409 Conflict\r\n"), "{}", response);
+        assert_eq!(body(&response), r#"{"error":"Recorder is not running","kind":"state"}"#);
+
+        let bitrate = "PUT /bitrate HTTP/1.1\r\nContent-Length: 10\r\n\r\n{\"kbps\":0}";
+        let response = exchange(&stopped(), bitrate);
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+        assert!(body(&response).ends_with(r#""kind":"config"}"#), "{}", response);
+
+        let response = exchange(&stopped(), "PUT /bitrate HTTP/1.1\r\nContent-Length: 4\r\n\r\nfast");
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+        assert!(body(&response).ends_with(r#""kind":"request"}"#), "{}", response);
+    }
+
+    #[test]
+    fn rejects_unknown_paths_and_methods() {
+        let response = exchange(&stopped(), "GET /start HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"), "{}", response);
+        let response = exchange(&stopped(), "GET /metrics HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", response);
+    }
+}
@@ -0,0 +1,238 @@
+use gstreamer as gst;
+use gst::prelude::*;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::error::InvalidSetting;
+
+/// Frames a window needs before its corruption rate counts, so a corrupt
+/// frame right after the window starts doesn't look like 100%.
+const MIN_FRAMES: u64 = 30;
+
+/// How many corrupt camera frames get dropped before recording fails.
+///
+/// Frames `jpegdec` can't decode are dropped and counted, the recorder
+/// only fails once more than `max_percent` of the frames received within
+/// `window` were corrupt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptionPolicy {
+    /// 100 never fails, 0 fails on the first corrupt frame.
+    pub max_percent: u32,
+    pub window: Duration,
+    /// Corrupt frames are reported at most this often.
+    pub report_interval: Duration,
+}
+
+impl Default for CorruptionPolicy {
+    fn default() -> Self {
+        CorruptionPolicy {
+            max_percent: 10,
+            window: Duration::from_secs(10),
+            report_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+impl CorruptionPolicy {
+    pub fn validate(&self) -> Result<(), InvalidSetting> {
+        if self.max_percent > 100 {
+            return Err(InvalidSetting::new("corruption.max_percent", "must not be greater than 100"));
+        }
+        if self.window.as_millis() == 0 {
+            return Err(InvalidSetting::new("corruption.window", "must be greater than 0"));
+        }
+        Ok(())
+    }
+}
+
+/// What a corrupt frame amounts to.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Verdict {
+    /// Corrupt frames to report now, including earlier unreported ones.
+    pub report: Option<u64>,
+    /// Set to the corrupt and received frames of the current window when
+    /// they exceed the policy.
+    pub exceeded: Option<(u64, u64)>,
+}
+
+/// Counts the frames the decoder of a running pipeline receives and the
+/// corrupt ones it drops.
+pub struct CorruptionMonitor {
+    policy: CorruptionPolicy,
+    frames: Arc<AtomicU64>,
+    window_start: Instant,
+    window_frames: u64,
+    window_corrupt: u64,
+    last_report: Option<Instant>,
+    unreported: u64,
+}
+
+impl CorruptionMonitor {
+    /// Starts counting the frames going into `jpegdec`, `None` if
+    /// `pipeline` doesn't decode MJPEG.
+    pub fn attach(pipeline: &gst::Pipeline, policy: &CorruptionPolicy) -> Option<CorruptionMonitor> {
+        let pad = pipeline.get_by_name("jpegdec")?.get_static_pad("sink")?;
+
+        let frames = Arc::new(AtomicU64::new(0));
+        let probe_frames = frames.clone();
+        pad.add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+            probe_frames.fetch_add(1, Ordering::Relaxed);
+            gst::PadProbeReturn::Ok
+        });
+
+        Some(CorruptionMonitor::new(policy, frames))
+    }
+
+    /// Counts the corrupt frames among those added to `frames`.
+    fn new(policy: &CorruptionPolicy, frames: Arc<AtomicU64>) -> CorruptionMonitor {
+        CorruptionMonitor {
+            policy: policy.clone(),
+            frames,
+            window_start: Instant::now(),
+            window_frames: 0,
+            window_corrupt: 0,
+            last_report: None,
+            unreported: 0,
+        }
+    }
+
+    /// Whether a message from `src` is about a corrupt frame.
+    pub fn is_corrupt_frame(src: Option<&gst::Object>, error: &glib::Error) -> bool {
+        src.map(|src| src.get_name().as_str() == "jpegdec").unwrap_or(false)
+            && error.kind::<gst::StreamError>() == Some(gst::StreamError::Decode)
+    }
+
+    /// Called for every corrupt frame the decoder dropped.
+    pub fn record(&mut self) -> Verdict {
+        let now = Instant::now();
+        let frames = self.frames.load(Ordering::Relaxed);
+        if now.duration_since(self.window_start) >= self.policy.window {
+            self.window_start = now;
+            self.window_frames = frames;
+            self.window_corrupt = 0;
+        }
+        self.window_corrupt += 1;
+        self.unreported += 1;
+
+        let mut verdict = Verdict {
+            report: self.report(now),
+            ..Verdict::default()
+        };
+
+        let received = frames.saturating_sub(self.window_frames).max(self.window_corrupt);
+        let too_many = self.window_corrupt * 100 > received * u64::from(self.policy.max_percent);
+        if too_many && (received >= MIN_FRAMES || self.policy.max_percent == 0) {
+            verdict.exceeded = Some((self.window_corrupt, received));
+        }
+
+        verdict
+    }
+
+    /// Corrupt frames left unreported once the report interval is over,
+    /// so they don't wait for the next corrupt frame. Meant to be called
+    /// periodically.
+    pub fn flush(&mut self) -> Option<u64> {
+        if self.unreported == 0 {
+            return None;
+        }
+        self.report(Instant::now())
+    }
+
+    fn report(&mut self, now: Instant) -> Option<u64> {
+        let due = self.last_report
+            .map(|last| now.duration_since(last) >= self.policy.report_interval)
+            .unwrap_or(true);
+        if !due {
+            return None;
+        }
+        self.last_report = Some(now);
+        Some(std::mem::replace(&mut self.unreported, 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    fn monitor(max_percent: u32, report_interval: Duration) -> (CorruptionMonitor, Arc<AtomicU64>) {
+        let policy = CorruptionPolicy {
+            max_percent,
+            window: Duration::from_secs(60),
+            report_interval,
+        };
+        let frames = Arc::new(AtomicU64::new(0));
+        (CorruptionMonitor::new(&policy, frames.clone()), frames)
+    }
+
+    #[test]
+    fn fails_above_the_rate() {
+        let (mut monitor, frames) = monitor(10, Duration::from_secs(60));
+        frames.store(100, Ordering::Relaxed);
+
+        for _ in 0..10 {
+            assert_eq!(monitor.record().exceeded, None);
+        }
+        assert_eq!(monitor.record().exceeded, Some((11, 100)));
+    }
+
+    #[test]
+    fn needs_enough_frames_before_failing() {
+        let (mut monitor, frames) = monitor(10, Duration::from_secs(60));
+        frames.store(MIN_FRAMES - 10, Ordering::Relaxed);
+
+        for _ in 0..10 {
+            assert_eq!(monitor.record().exceeded, None);
+        }
+        frames.store(MIN_FRAMES, Ordering::Relaxed);
+        assert_eq!(monitor.record().exceeded, Some((11, MIN_FRAMES)));
+    }
+
+    #[test]
+    fn fails_on_the_first_corrupt_frame_at_zero_percent() {
+        let (mut monitor, frames) = monitor(0, Duration::from_secs(60));
+        frames.store(1, Ordering::Relaxed);
+
+        assert_eq!(monitor.record().exceeded, Some((1, 1)));
+    }
+
+    #[test]
+    fn never_fails_at_a_hundred_percent() {
+        let (mut monitor, frames) = monitor(100, Duration::from_secs(60));
+        frames.store(MIN_FRAMES, Ordering::Relaxed);
+
+        for _ in 0..2 * MIN_FRAMES {
+            assert_eq!(monitor.record().exceeded, None);
+        }
+    }
+
+    #[test]
+    fn reports_at_most_once_per_interval() {
+        let (mut monitor, frames) = monitor(100, Duration::from_millis(50));
+        frames.store(100, Ordering::Relaxed);
+
+        assert_eq!(monitor.record().report, Some(1));
+        assert_eq!(monitor.record().report, None);
+        assert_eq!(monitor.record().report, None);
+        assert_eq!(monitor.flush(), None);
+
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(monitor.record().report, Some(3));
+    }
+
+    #[test]
+    fn flushes_unreported_frames_after_the_interval() {
+        let (mut monitor, frames) = monitor(100, Duration::from_millis(50));
+        frames.store(100, Ordering::Relaxed);
+
+        assert_eq!(monitor.record().report, Some(1));
+        assert_eq!(monitor.record().report, None);
+
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(monitor.flush(), Some(1));
+        assert_eq!(monitor.flush(), None);
+    }
+}
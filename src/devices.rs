@@ -0,0 +1,159 @@
+use gstreamer as gst;
+use gst::prelude::*;
+
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+
+use crate::error::RecorderError;
+use crate::modes::{self, ModeConstraints, VideoMode};
+use crate::pipeline::make_element;
+
+/// A video capture device found by the device monitor.
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+    pub name: String,
+    /// Device node, e.g. `/dev/video0`, when the provider exposes it.
+    pub path: Option<String>,
+    /// Serial number reported by udev, stable across re-plugging.
+    pub serial: Option<String>,
+    pub caps: Option<gst::Caps>,
+}
+
+/// Lists all video capture devices currently present.
+pub fn list() -> Result<Vec<DeviceInfo>, RecorderError> {
+    gst::init()?;
+
+    let monitor = gst::DeviceMonitor::new();
+    monitor.add_filter("Video/Source", None);
+    monitor.start()?;
+
+    let devices = monitored(&monitor);
+
+    monitor.stop();
+
+    Ok(devices)
+}
+
+/// The devices a started `monitor` currently sees.
+pub fn monitored(monitor: &gst::DeviceMonitor) -> Vec<DeviceInfo> {
+    monitor.get_devices()
+        .iter()
+        .map(|device| DeviceInfo {
+            name: device.get_display_name().to_string(),
+            path: device.get_properties()
+                .and_then(|props| props.get::<String>("device.path")),
+            serial: device.get_properties()
+                .and_then(|props| props.get::<String>("device.serial")),
+            caps: device.get_caps(),
+        })
+        .collect()
+}
+
+/// Current device node of the camera `device` stands for: a device path,
+/// possibly a stable symlink like `/dev/v4l/by-id/...`, or
+/// `serial:<serial>`.
+pub fn resolve(device: &str) -> Result<String, RecorderError> {
+    let not_found = || RecorderError::DeviceNotFound(device.into());
+
+    if let Some(serial) = device.strip_prefix("serial:") {
+        return path_of_serial(list()?, serial).ok_or_else(not_found);
+    }
+
+    fs::canonicalize(device)
+        .map(|path| path.to_string_lossy().into_owned())
+        .map_err(|_| not_found())
+}
+
+/// Whether the camera `device` stands for is plugged in, see [`resolve`].
+pub fn is_present(device: &str) -> bool {
+    resolve(device).is_ok()
+}
+
+/// Like [`is_present`], looking `serial:` devices up in the started
+/// `monitor` rather than starting one for every check.
+pub fn is_present_in(monitor: &gst::DeviceMonitor, device: &str) -> bool {
+    match device.strip_prefix("serial:") {
+        Some(serial) => path_of_serial(monitored(monitor), serial).is_some(),
+        None => is_present(device),
+    }
+}
+
+fn path_of_serial(devices: Vec<DeviceInfo>, serial: &str) -> Option<String> {
+    devices.into_iter()
+        .find(|info| info.serial.as_deref() == Some(serial))
+        .and_then(|info| info.path)
+}
+
+/// Opens `device` and returns every caps its source pad can produce.
+pub fn probe_caps(device: &str) -> Result<gst::Caps, RecorderError> {
+    gst::init()?;
+
+    let path = resolve(device)?;
+    let v4l2src = make_element("v4l2src", None)?;
+    v4l2src.set_property("device", &path)?;
+
+    // the device is only opened in READY, before that we would get the
+    // template caps
+    if v4l2src.set_state(gst::State::Ready).is_err() {
+        return Err(open_failure(device, &path));
+    }
+    let caps = v4l2src.get_static_pad("src")
+        .and_then(|pad| pad.query_caps(None));
+    v4l2src.set_state(gst::State::Null)?;
+
+    Ok(caps.unwrap_or_else(gst::Caps::new_empty))
+}
+
+/// Why `path`, the node of the camera `device` stands for, couldn't be
+/// opened. The failed state change doesn't tell, so it's opened again.
+fn open_failure(device: &str, path: &str) -> RecorderError {
+    if !Path::new(path).exists() {
+        return RecorderError::DeviceNotFound(device.into());
+    }
+    let reason = match OpenOptions::new().read(true).write(true).open(path) {
+        Ok(_) => String::from("v4l2src failed to open it"),
+        Err(err) => err.to_string(),
+    };
+    RecorderError::DeviceInaccessible { device: path.into(), reason }
+}
+
+/// Every mode `device` supports, see [`modes::modes_from_caps`].
+pub fn probe_modes(device: &str) -> Result<Vec<VideoMode>, RecorderError> {
+    let caps = probe_caps(device)?;
+    Ok(modes::modes_from_caps(&caps))
+}
+
+/// Probes `device` and picks its best mode satisfying `constraints`.
+pub fn select_mode(device: &str, constraints: &ModeConstraints) -> Result<VideoMode, RecorderError> {
+    let modes = probe_modes(device)?;
+    constraints.select(&modes)
+        .cloned()
+        .ok_or_else(|| RecorderError::NoMatchingMode {
+            device: device.into(),
+            constraints: constraints.clone(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::error::ErrorKind;
+    use crate::testutil::temp_dir;
+
+    #[test]
+    fn tells_missing_from_inaccessible_devices() {
+        let dir = temp_dir("devices");
+        let missing = dir.join("video9");
+        let failure = open_failure("/dev/v4l/by-id/cam", &missing.to_string_lossy());
+        assert!(matches!(&failure, RecorderError::DeviceNotFound(device) if device == "/dev/v4l/by-id/cam"));
+
+        // directories can't be opened for writing
+        let failure = open_failure("/dev/v4l/by-id/cam", &dir.to_string_lossy());
+        assert_eq!(failure.kind(), ErrorKind::Device);
+        match failure {
+            RecorderError::DeviceInaccessible { device, .. } => assert_eq!(device, dir.to_string_lossy()),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
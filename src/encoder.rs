@@ -0,0 +1,551 @@
+use gstreamer as gst;
+use gst::prelude::*;
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::error::{InvalidSetting, RecorderError};
+use crate::pipeline::make_element;
+
+const FILTER: &str = "capsfilter";
+
+const X264_PROFILES: &[&str] = &[
+    "constrained-baseline",
+    "baseline",
+    "main",
+    "high",
+    "high-10",
+    "high-4:2:2",
+    "high-4:4:4",
+];
+
+const X265_PROFILES: &[&str] = &[
+    "main",
+    "main-still-picture",
+    "main-intra",
+    "main-444",
+    "main-444-intra",
+    "main-10",
+    "main-10-intra",
+    "main-422-10",
+    "main-422-10-intra",
+    "main-444-10",
+    "main-444-10-intra",
+    "main-12",
+    "main-12-intra",
+    "main-422-12",
+    "main-422-12-intra",
+    "main-444-12",
+    "main-444-12-intra",
+];
+
+const VPX_PROFILES: &[&str] = &["0", "1", "2", "3"];
+
+const X26X_SPEED_PRESETS: &[&str] = &[
+    "ultrafast",
+    "superfast",
+    "veryfast",
+    "faster",
+    "fast",
+    "medium",
+    "slow",
+    "slower",
+    "veryslow",
+    "placebo",
+];
+
+const X264_TUNES: &[&str] = &["stillimage", "fastdecode", "zerolatency"];
+const X265_TUNES: &[&str] = &["psnr", "ssim", "grain", "zerolatency", "fastdecode", "animation"];
+const VPX_TUNES: &[&str] = &["psnr", "ssim"];
+
+/// Video encoder used for recording.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoder {
+    #[serde(alias = "h264")]
+    X264,
+    #[serde(alias = "h265", alias = "hevc")]
+    X265,
+    Vp8,
+    Vp9,
+    Av1,
+    #[serde(alias = "jpeg")]
+    Mjpeg,
+}
+
+impl Encoder {
+    /// Element factories able to do this encoding, in order of preference.
+    pub fn factory_names(self) -> &'static [&'static str] {
+        match self {
+            Encoder::X264 => &["x264enc"],
+            Encoder::X265 => &["x265enc"],
+            Encoder::Vp8 => &["vp8enc"],
+            Encoder::Vp9 => &["vp9enc"],
+            Encoder::Av1 => &["rav1enc", "av1enc"],
+            Encoder::Mjpeg => &["jpegenc"],
+        }
+    }
+
+    /// Media type of the encoded stream.
+    pub fn caps_name(self) -> &'static str {
+        match self {
+            Encoder::X264 => "video/x-h264",
+            Encoder::X265 => "video/x-h265",
+            Encoder::Vp8 => "video/x-vp8",
+            Encoder::Vp9 => "video/x-vp9",
+            Encoder::Av1 => "video/x-av1",
+            Encoder::Mjpeg => "image/jpeg",
+        }
+    }
+
+    /// Format of a stream with media type `name`, see [`Encoder::caps_name`].
+    pub fn from_caps_name(name: &str) -> Option<Encoder> {
+        [Encoder::X264, Encoder::X265, Encoder::Vp8, Encoder::Vp9, Encoder::Av1, Encoder::Mjpeg]
+            .iter()
+            .cloned()
+            .find(|format| format.caps_name() == name)
+    }
+
+    /// Parser put between encoder and muxer, if the format needs one.
+    pub fn parser(self) -> Option<&'static str> {
+        match self {
+            Encoder::X264 => Some("h264parse"),
+            Encoder::X265 => Some("h265parse"),
+            Encoder::Vp8 | Encoder::Vp9 => None,
+            Encoder::Av1 => Some("av1parse"),
+            Encoder::Mjpeg => Some("jpegparse"),
+        }
+    }
+
+    fn profiles(self) -> Option<&'static [&'static str]> {
+        match self {
+            Encoder::X264 => Some(X264_PROFILES),
+            Encoder::X265 => Some(X265_PROFILES),
+            Encoder::Vp8 | Encoder::Vp9 => Some(VPX_PROFILES),
+            Encoder::Av1 | Encoder::Mjpeg => None,
+        }
+    }
+
+    fn tunes(self) -> Option<&'static [&'static str]> {
+        match self {
+            Encoder::X264 => Some(X264_TUNES),
+            Encoder::X265 => Some(X265_TUNES),
+            Encoder::Vp8 | Encoder::Vp9 => Some(VPX_TUNES),
+            Encoder::Av1 | Encoder::Mjpeg => None,
+        }
+    }
+
+    /// Rate controls the encoder elements implement. Without one, x265enc
+    /// and rav1enc encode at an average bitrate when one is set.
+    fn rate_controls(self) -> &'static [RateControl] {
+        match self {
+            Encoder::X264 | Encoder::Vp8 | Encoder::Vp9 => {
+                &[RateControl::Cbr, RateControl::Vbr, RateControl::ConstantQuality]
+            }
+            // rav1enc, unlike av1enc, can't be told cbr from vbr
+            Encoder::X265 | Encoder::Av1 | Encoder::Mjpeg => &[RateControl::ConstantQuality],
+        }
+    }
+
+    /// Sets the target bitrate on an encoder element built from this kind,
+    /// also while it is playing.
+    pub fn set_bitrate(self, element: &gst::Element, kbps: u32) -> Result<(), RecorderError> {
+        match bitrate_property(element) {
+            Some((property, unit)) => set_arg(element, property, u64::from(kbps) * 1000 / unit),
+            None => Err(RecorderError::MissingProperty { element: factory_name(element), property: "bitrate" }),
+        }
+    }
+}
+
+/// Current target bitrate of an encoder element in kbit/s.
+pub fn current_bitrate(element: &gst::Element) -> Option<u32> {
+    let (property, unit) = bitrate_property(element)?;
+    let value = element.get_property(property).ok()?;
+    // the property types differ between encoders
+    let raw = value.get::<u32>().map(u64::from)
+        .or_else(|| value.get::<i32>().map(|v| v.max(0) as u64))
+        .or_else(|| value.get::<u64>())
+        .or_else(|| value.get::<i64>().map(|v| v.max(0) as u64))?;
+    Some((raw * unit / 1000) as u32)
+}
+
+/// Name of the target bitrate property of an encoder element, and how many
+/// bit/s one unit of it stands for.
+pub fn bitrate_property(element: &gst::Element) -> Option<(&'static str, u64)> {
+    match factory_name(element).as_str() {
+        "x264enc" | "x265enc" => Some(("bitrate", 1000)),
+        "vp8enc" | "vp9enc" => Some(("target-bitrate", 1)),
+        "av1enc" => Some(("target-bitrate", 1000)),
+        "rav1enc" => Some(("bitrate", 1)),
+        _ => None,
+    }
+}
+
+impl FromStr for Encoder {
+    type Err = RecorderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "x264" | "h264" => Ok(Encoder::X264),
+            "x265" | "h265" | "hevc" => Ok(Encoder::X265),
+            "vp8" => Ok(Encoder::Vp8),
+            "vp9" => Ok(Encoder::Vp9),
+            "av1" => Ok(Encoder::Av1),
+            "mjpeg" | "jpeg" => Ok(Encoder::Mjpeg),
+            _ => Err(RecorderError::UnknownValue { kind: "encoder", value: s.into() }),
+        }
+    }
+}
+
+impl fmt::Display for Encoder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Encoder::X264 => "x264",
+            Encoder::X265 => "x265",
+            Encoder::Vp8 => "vp8",
+            Encoder::Vp9 => "vp9",
+            Encoder::Av1 => "av1",
+            Encoder::Mjpeg => "mjpeg",
+        })
+    }
+}
+
+/// How the encoder spends bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateControl {
+    /// Constant bitrate at `bitrate`.
+    Cbr,
+    /// Variable bitrate, `bitrate` being the target or upper bound.
+    Vbr,
+    /// Constant quality at `quantizer`, ignoring `bitrate`.
+    #[serde(alias = "cq")]
+    ConstantQuality,
+}
+
+impl fmt::Display for RateControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            RateControl::Cbr => "cbr",
+            RateControl::Vbr => "vbr",
+            RateControl::ConstantQuality => "constantquality",
+        })
+    }
+}
+
+/// Encoder kind and its tuning. Unset options keep the element defaults.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncoderConfig {
+    pub kind: Encoder,
+    /// Target bitrate in kbit/s.
+    pub bitrate: Option<u32>,
+    pub rate_control: Option<RateControl>,
+    /// Quantizer for constant quality, or JPEG quality for MJPEG.
+    pub quantizer: Option<u32>,
+    /// Preset name for x264/x265, CPU usage level for VP8/VP9/AV1.
+    pub speed_preset: Option<String>,
+    pub tune: Option<String>,
+    pub profile: Option<String>,
+    /// Maximum number of frames between two keyframes.
+    pub key_int_max: u32,
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        EncoderConfig {
+            kind: Encoder::X264,
+            bitrate: None,
+            rate_control: None,
+            quantizer: None,
+            speed_preset: None,
+            tune: None,
+            profile: None,
+            key_int_max: 10,
+        }
+    }
+}
+
+impl EncoderConfig {
+    /// Profile forced on the encoder output, x264 has always been pinned to
+    /// high.
+    pub fn effective_profile(&self) -> Option<&str> {
+        match (&self.profile, self.kind) {
+            (Some(profile), _) => Some(profile),
+            (None, Encoder::X264) => Some("high"),
+            (None, _) => None,
+        }
+    }
+
+    /// Checks that every option set is supported by the chosen encoder.
+    pub fn validate(&self) -> Result<(), InvalidSetting> {
+        let kind = self.kind;
+        let one_of = |key: &str, value: &str, allowed: Option<&[&str]>| match allowed {
+            Some(allowed) if allowed.contains(&value) => Ok(()),
+            Some(allowed) => Err(InvalidSetting::new(
+                key,
+                format!("unknown value {} for {}, expected one of {}", value, kind, allowed.join(", ")),
+            )),
+            None => Err(InvalidSetting::new(key, format!("not supported by {}", kind))),
+        };
+
+        if self.bitrate == Some(0) {
+            return Err(InvalidSetting::new("encoder.bitrate", "must be greater than 0"));
+        }
+        if self.bitrate.is_some() && kind == Encoder::Mjpeg {
+            return Err(InvalidSetting::new("encoder.bitrate", "not supported by mjpeg"));
+        }
+        if self.key_int_max == 0 {
+            return Err(InvalidSetting::new("encoder.key_int_max", "must be greater than 0"));
+        }
+
+        if let Some(rate_control) = self.rate_control {
+            if !kind.rate_controls().contains(&rate_control) {
+                return Err(InvalidSetting::new(
+                    "encoder.rate_control",
+                    format!("{} not supported by {}", rate_control, kind),
+                ));
+            }
+            if rate_control == RateControl::ConstantQuality && self.quantizer.is_none() {
+                return Err(InvalidSetting::new("encoder.quantizer", "required for constant quality"));
+            }
+        }
+        if let Some(quantizer) = self.quantizer {
+            let max = if kind == Encoder::Mjpeg { 100 } else { 255 };
+            if quantizer > max {
+                return Err(InvalidSetting::new("encoder.quantizer", format!("must be at most {}", max)));
+            }
+        }
+
+        if let Some(speed_preset) = &self.speed_preset {
+            match kind {
+                Encoder::X264 | Encoder::X265 => {
+                    one_of("encoder.speed_preset", speed_preset, Some(X26X_SPEED_PRESETS))?
+                }
+                Encoder::Vp8 | Encoder::Vp9 | Encoder::Av1 => {
+                    if speed_preset.parse::<i32>().is_err() {
+                        return Err(InvalidSetting::new(
+                            "encoder.speed_preset",
+                            format!("{} expects a CPU usage level, got {}", kind, speed_preset),
+                        ));
+                    }
+                }
+                Encoder::Mjpeg => one_of("encoder.speed_preset", speed_preset, None)?,
+            }
+        }
+        if let Some(tune) = &self.tune {
+            // x264 takes combinations like zerolatency+fastdecode
+            for part in tune.split('+') {
+                one_of("encoder.tune", part, kind.tunes())?;
+            }
+        }
+        if let Some(profile) = &self.profile {
+            one_of("encoder.profile", profile, kind.profiles())?;
+        }
+
+        Ok(())
+    }
+
+    /// Factories [`EncoderConfig::build`] picks from.
+    pub(crate) fn factories(&self) -> Vec<Vec<&'static str>> {
+        let mut factories = vec![self.kind.factory_names().to_vec(), vec![FILTER]];
+        factories.extend(self.kind.parser().map(|parser| vec![parser]));
+        factories
+    }
+
+    /// Builds encoder, caps filter and parser, to be linked in that order.
+    pub fn build(&self) -> Result<Vec<gst::Element>, RecorderError> {
+        let encoder = self.make_encoder()?;
+        let factory = factory_name(&encoder);
+
+        if let Some(bitrate) = self.bitrate {
+            self.kind.set_bitrate(&encoder, bitrate)?;
+        }
+
+        match factory.as_str() {
+            "x264enc" => {
+                set_arg(&encoder, "key-int-max", self.key_int_max)?;
+                match self.rate_control {
+                    Some(RateControl::Cbr) => set_arg(&encoder, "pass", "cbr")?,
+                    Some(RateControl::Vbr) => set_arg(&encoder, "pass", "qual")?,
+                    Some(RateControl::ConstantQuality) => set_arg(&encoder, "pass", "quant")?,
+                    None => (),
+                }
+                if let Some(quantizer) = self.quantizer {
+                    set_arg(&encoder, "quantizer", quantizer)?;
+                }
+                if let Some(speed_preset) = &self.speed_preset {
+                    set_arg(&encoder, "speed-preset", speed_preset)?;
+                }
+                if let Some(tune) = &self.tune {
+                    set_arg(&encoder, "tune", tune)?;
+                }
+            }
+            "x265enc" => {
+                set_arg(&encoder, "key-int-max", self.key_int_max)?;
+                if let (Some(RateControl::ConstantQuality), Some(quantizer)) = (self.rate_control, self.quantizer) {
+                    set_arg(&encoder, "qp", quantizer)?;
+                }
+                if let Some(speed_preset) = &self.speed_preset {
+                    set_arg(&encoder, "speed-preset", speed_preset)?;
+                }
+                if let Some(tune) = &self.tune {
+                    set_arg(&encoder, "tune", tune)?;
+                }
+            }
+            "vp8enc" | "vp9enc" | "av1enc" => {
+                set_arg(&encoder, "keyframe-max-dist", self.key_int_max)?;
+                match self.rate_control {
+                    Some(RateControl::Cbr) => set_arg(&encoder, "end-usage", "cbr")?,
+                    Some(RateControl::Vbr) => set_arg(&encoder, "end-usage", "vbr")?,
+                    Some(RateControl::ConstantQuality) => {
+                        set_arg(&encoder, "end-usage", "cq")?;
+                        if let Some(quantizer) = self.quantizer {
+                            set_arg(&encoder, "cq-level", quantizer)?;
+                        }
+                    }
+                    None => (),
+                }
+                if let Some(speed_preset) = &self.speed_preset {
+                    set_arg(&encoder, "cpu-used", speed_preset)?;
+                }
+                if let Some(tune) = &self.tune {
+                    set_arg(&encoder, "tuning", tune)?;
+                }
+            }
+            "rav1enc" => {
+                set_arg(&encoder, "max-key-frame-interval", self.key_int_max)?;
+                if let (Some(RateControl::ConstantQuality), Some(quantizer)) = (self.rate_control, self.quantizer) {
+                    set_arg(&encoder, "quantizer", quantizer)?;
+                }
+                if let Some(speed_preset) = &self.speed_preset {
+                    set_arg(&encoder, "speed-preset", speed_preset)?;
+                }
+            }
+            "jpegenc" => {
+                if let Some(quantizer) = self.quantizer {
+                    set_arg(&encoder, "quality", quantizer)?;
+                }
+            }
+            _ => unreachable!("encoder built from an unknown factory"),
+        }
+
+        // encoder filter
+        let encoder_filter = make_element(FILTER, "encoder_filter")?;
+        let mut encode_caps = gst::Caps::builder(self.kind.caps_name());
+        if let Some(profile) = self.effective_profile() {
+            encode_caps = encode_caps.field("profile", &profile);
+        }
+        encoder_filter.set_property("caps", &encode_caps.build())?;
+
+        let mut elements = vec![encoder, encoder_filter];
+
+        // parser
+        if let Some(parser) = self.kind.parser() {
+            elements.push(make_element(parser, "parser")?);
+        }
+
+        Ok(elements)
+    }
+
+    fn make_encoder(&self) -> Result<gst::Element, RecorderError> {
+        let factories = self.kind.factory_names();
+        for factory in factories {
+            if let Ok(encoder) = make_element(factory, "encoder") {
+                return Ok(encoder);
+            }
+        }
+        Err(RecorderError::MissingElement(factories[0]))
+    }
+}
+
+fn factory_name(element: &gst::Element) -> String {
+    element.get_factory()
+        .map(|factory| factory.get_name().to_string())
+        .unwrap_or_default()
+}
+
+/// Sets `name` from its string form, which copes with the property types
+/// differing between encoders and GStreamer versions.
+fn set_arg<V: ToString>(element: &gst::Element, name: &'static str, value: V) -> Result<(), RecorderError> {
+    if element.find_property(name).is_none() {
+        return Err(RecorderError::MissingProperty {
+            element: factory_name(element),
+            property: name,
+        });
+    }
+    element.set_property_from_str(name, &value.to_string());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testutil::rejected;
+
+    #[test]
+    fn accepts_the_defaults() {
+        for &kind in &[Encoder::X264, Encoder::X265, Encoder::Vp8, Encoder::Vp9, Encoder::Av1, Encoder::Mjpeg] {
+            assert!(EncoderConfig { kind, ..EncoderConfig::default() }.validate().is_ok(), "{}", kind);
+        }
+    }
+
+    #[test]
+    fn rejects_rate_controls_the_encoder_lacks() {
+        let cbr = EncoderConfig {
+            kind: Encoder::X265,
+            rate_control: Some(RateControl::Cbr),
+            bitrate: Some(2000),
+            ..EncoderConfig::default()
+        };
+        assert_eq!(rejected(cbr.validate()).key, "encoder.rate_control");
+
+        let mut constant_quality = EncoderConfig {
+            rate_control: Some(RateControl::ConstantQuality),
+            ..EncoderConfig::default()
+        };
+        assert_eq!(rejected(constant_quality.validate()).key, "encoder.quantizer");
+        constant_quality.quantizer = Some(23);
+        assert!(constant_quality.validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_out_of_range_values() {
+        let zero_bitrate = EncoderConfig { bitrate: Some(0), ..EncoderConfig::default() };
+        assert_eq!(rejected(zero_bitrate.validate()).key, "encoder.bitrate");
+        let no_keyframes = EncoderConfig { key_int_max: 0, ..EncoderConfig::default() };
+        assert_eq!(rejected(no_keyframes.validate()).key, "encoder.key_int_max");
+
+        let mjpeg = EncoderConfig { kind: Encoder::Mjpeg, ..EncoderConfig::default() };
+        let quality = EncoderConfig { quantizer: Some(101), ..mjpeg.clone() };
+        assert_eq!(rejected(quality.validate()).key, "encoder.quantizer");
+        let bitrate = EncoderConfig { bitrate: Some(2000), ..mjpeg };
+        assert_eq!(rejected(bitrate.validate()).key, "encoder.bitrate");
+    }
+
+    #[test]
+    fn rejects_unknown_presets_tunes_and_profiles() {
+        let speed_preset = |kind, preset: &str| EncoderConfig {
+            kind,
+            speed_preset: Some(String::from(preset)),
+            ..EncoderConfig::default()
+        };
+        assert_eq!(rejected(speed_preset(Encoder::X264, "warp").validate()).key, "encoder.speed_preset");
+        assert_eq!(rejected(speed_preset(Encoder::Vp9, "fast").validate()).key, "encoder.speed_preset");
+        assert!(speed_preset(Encoder::Vp9, "4").validate().is_ok());
+
+        let tune = |tune: &str| EncoderConfig { tune: Some(String::from(tune)), ..EncoderConfig::default() };
+        assert!(tune("zerolatency+fastdecode").validate().is_ok());
+        assert_eq!(rejected(tune("zerolatency+loud").validate()).key, "encoder.tune");
+
+        let profile = |kind, profile: &str| EncoderConfig {
+            kind,
+            profile: Some(String::from(profile)),
+            ..EncoderConfig::default()
+        };
+        assert_eq!(rejected(profile(Encoder::X264, "extreme").validate()).key, "encoder.profile");
+        assert_eq!(rejected(profile(Encoder::Av1, "main").validate()).key, "encoder.profile");
+    }
+}
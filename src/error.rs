@@ -0,0 +1,282 @@
+use gstreamer as gst;
+use gst::prelude::*;
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+
+use crate::modes::ModeConstraints;
+
+/// What an error is about, to decide how to react without matching every
+/// [`RecorderError`] variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// The configuration or settings are wrong, fix them.
+    Config,
+    /// A GStreamer plugin needs to be installed.
+    MissingPlugin,
+    /// The camera is missing, busy or can't do what is asked.
+    Device,
+    /// The encoder can't be set up as configured.
+    Encoder,
+    /// The output filesystem failed.
+    Storage,
+    /// The running pipeline posted an error, or its bus couldn't be watched.
+    Bus,
+    /// GStreamer failed building or driving the pipeline.
+    Pipeline,
+    /// The recorder is not in the right state for the call.
+    State,
+}
+
+/// Every error of this crate.
+#[derive(Debug)]
+pub enum RecorderError {
+    /// A setting is missing, malformed or out of range.
+    Config(InvalidSetting),
+    /// The settings file couldn't be read or parsed.
+    SettingsFile { path: String, reason: String },
+    /// The settings file defines no such camera.
+    UnknownCamera { path: String, camera: String },
+    /// The settings file defines several cameras and none was selected.
+    AmbiguousCamera { path: String, cameras: String },
+    /// `value` names no known `kind`, e.g. an encoder or container.
+    UnknownValue { kind: &'static str, value: String },
+    /// An endpoint couldn't listen on `address`.
+    Listen { address: String, reason: String },
+    /// No installed plugin provides the element.
+    MissingElement(&'static str),
+    /// No camera is plugged in for the given device or serial.
+    DeviceNotFound(String),
+    /// The device can't be opened, e.g. for lack of permissions.
+    DeviceInaccessible { device: String, reason: String },
+    /// None of the camera's modes satisfies the constraints.
+    NoMatchingMode { device: String, constraints: ModeConstraints },
+    /// Passthrough recording needs a V4L2 camera, the source is given.
+    PassthroughUnsupported(String),
+    /// The encoder element lacks a property it is configured through.
+    MissingProperty { element: String, property: &'static str },
+    /// Preparing or cleaning up the output directory failed.
+    Storage(io::Error),
+    /// The pipeline posted an error.
+    Bus(ErrorMessage),
+    /// The pipeline bus couldn't be watched.
+    Watch,
+    /// GStreamer failed building the pipeline or changing its state.
+    Pipeline(String),
+    /// [`crate::CameraRecorder::start`] was called while recording.
+    AlreadyRunning,
+    /// The call needs a started recorder.
+    NotRunning,
+}
+
+impl RecorderError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            RecorderError::Config(_)
+            | RecorderError::SettingsFile { .. }
+            | RecorderError::UnknownCamera { .. }
+            | RecorderError::AmbiguousCamera { .. }
+            | RecorderError::UnknownValue { .. }
+            | RecorderError::Listen { .. } => ErrorKind::Config,
+            RecorderError::MissingElement(_) => ErrorKind::MissingPlugin,
+            RecorderError::Bus(err) if err.is_device_error() => ErrorKind::Device,
+            RecorderError::DeviceNotFound(_)
+            | RecorderError::DeviceInaccessible { .. }
+            | RecorderError::NoMatchingMode { .. }
+            | RecorderError::PassthroughUnsupported(_) => ErrorKind::Device,
+            RecorderError::MissingProperty { .. } => ErrorKind::Encoder,
+            RecorderError::Storage(_) => ErrorKind::Storage,
+            RecorderError::Bus(_) | RecorderError::Watch => ErrorKind::Bus,
+            RecorderError::Pipeline(_) => ErrorKind::Pipeline,
+            RecorderError::AlreadyRunning | RecorderError::NotRunning => ErrorKind::State,
+        }
+    }
+
+    pub(crate) fn pipeline<S: Into<String>>(reason: S) -> RecorderError {
+        RecorderError::Pipeline(reason.into())
+    }
+}
+
+impl fmt::Display for RecorderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecorderError::Config(err) => err.fmt(f),
+            RecorderError::SettingsFile { path, reason } => write!(f, "Cannot read {}: {}", path, reason),
+            RecorderError::UnknownCamera { path, camera } => write!(f, "No camera {} in {}", camera, path),
+            RecorderError::AmbiguousCamera { path, cameras } => {
+                write!(f, "{} defines several cameras, select one of: {}", path, cameras)
+            }
+            RecorderError::UnknownValue { kind, value } => write!(f, "Unknown {} {}", kind, value),
+            RecorderError::Listen { address, reason } => write!(f, "Cannot listen on {}: {}", address, reason),
+            RecorderError::MissingElement(element) => write!(f, "Missing element {}", element),
+            RecorderError::DeviceNotFound(device) => write!(f, "Device {} not found", device),
+            RecorderError::DeviceInaccessible { device, reason } => write!(f, "Cannot open {}: {}", device, reason),
+            RecorderError::NoMatchingMode { device, constraints } => {
+                write!(f, "No mode of {} matches {}", device, constraints)
+            }
+            RecorderError::PassthroughUnsupported(source) => {
+                write!(f, "Passthrough recording needs a V4L2 camera, got {}", source)
+            }
+            RecorderError::MissingProperty { element, property } => {
+                write!(f, "Element {} has no property {}", element, property)
+            }
+            RecorderError::Storage(err) => write!(f, "Storage error: {}", err),
+            RecorderError::Bus(err) => err.fmt(f),
+            RecorderError::Watch => f.write_str("Bus watch error"),
+            RecorderError::Pipeline(reason) => write!(f, "Pipeline error: {}", reason),
+            RecorderError::AlreadyRunning => f.write_str("Recorder is already running"),
+            RecorderError::NotRunning => f.write_str("Recorder is not running"),
+        }
+    }
+}
+
+impl StdError for RecorderError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            RecorderError::Config(err) => Some(err),
+            RecorderError::Storage(err) => Some(err),
+            RecorderError::Bus(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<InvalidSetting> for RecorderError {
+    fn from(err: InvalidSetting) -> Self {
+        RecorderError::Config(err)
+    }
+}
+
+impl From<ErrorMessage> for RecorderError {
+    fn from(err: ErrorMessage) -> Self {
+        RecorderError::Bus(err)
+    }
+}
+
+impl From<glib::Error> for RecorderError {
+    fn from(err: glib::Error) -> Self {
+        RecorderError::Pipeline(err.to_string())
+    }
+}
+
+impl From<glib::BoolError> for RecorderError {
+    fn from(err: glib::BoolError) -> Self {
+        RecorderError::Pipeline(err.to_string())
+    }
+}
+
+impl From<gst::StateChangeError> for RecorderError {
+    fn from(err: gst::StateChangeError) -> Self {
+        RecorderError::Pipeline(err.to_string())
+    }
+}
+
+impl From<gst::PadLinkError> for RecorderError {
+    fn from(err: gst::PadLinkError) -> Self {
+        RecorderError::Pipeline(err.to_string())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ErrorMessage {
+    pub src: String,
+    /// Factory of the element that posted the error, if an element did.
+    pub factory: Option<String>,
+    pub error: String,
+    pub debug: Option<String>,
+    pub cause: glib::Error,
+}
+
+impl ErrorMessage {
+    pub fn new(src: Option<gst::Object>, error: glib::Error, debug: Option<String>) -> ErrorMessage {
+        let factory = src.as_ref()
+            .and_then(|s| s.downcast_ref::<gst::Element>())
+            .and_then(|e| e.get_factory())
+            .map(|f| f.get_name().to_string());
+        ErrorMessage {
+            src: src
+                .map(|s| String::from(s.get_path_string()))
+                .unwrap_or_else(|| String::from("None")),
+            factory,
+            error: error.to_string(),
+            debug,
+            cause: error,
+        }
+    }
+
+    /// Whether the camera failed, e.g. it got unplugged or is busy, rather
+    /// than the pipeline.
+    pub fn is_device_error(&self) -> bool {
+        self.factory.as_deref() == Some("v4l2src") && self.cause.kind::<gst::ResourceError>().is_some()
+    }
+}
+
+impl fmt::Display for ErrorMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Received error from {}: {} (debug: {:?})", self.src, self.error, self.debug)
+    }
+}
+
+impl StdError for ErrorMessage {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&self.cause)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InvalidSetting {
+    pub key: String,
+    pub reason: String,
+}
+
+impl InvalidSetting {
+    pub fn new<K: Into<String>, R: Into<String>>(key: K, reason: R) -> InvalidSetting {
+        InvalidSetting {
+            key: key.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for InvalidSetting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid setting {}: {}", self.key, self.reason)
+    }
+}
+
+impl StdError for InvalidSetting {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_errors() {
+        let cases = vec![
+            (RecorderError::from(InvalidSetting::new("output.location", "must not be empty")), ErrorKind::Config),
+            (RecorderError::UnknownValue { kind: "encoder", value: "av1".into() }, ErrorKind::Config),
+            (RecorderError::Listen { address: "[::1]:80".into(), reason: "denied".into() }, ErrorKind::Config),
+            (RecorderError::MissingElement("x264enc"), ErrorKind::MissingPlugin),
+            (RecorderError::DeviceNotFound("/dev/video0".into()), ErrorKind::Device),
+            (
+                RecorderError::DeviceInaccessible { device: "/dev/video0".into(), reason: "busy".into() },
+                ErrorKind::Device,
+            ),
+            (
+                RecorderError::NoMatchingMode { device: "/dev/video0".into(), constraints: ModeConstraints::default() },
+                ErrorKind::Device,
+            ),
+            (RecorderError::PassthroughUnsupported("file".into()), ErrorKind::Device),
+            (RecorderError::MissingProperty { element: "vp8enc".into(), property: "tune" }, ErrorKind::Encoder),
+            (RecorderError::Storage(io::Error::from(io::ErrorKind::PermissionDenied)), ErrorKind::Storage),
+            (RecorderError::Watch, ErrorKind::Bus),
+            (RecorderError::pipeline("cannot link"), ErrorKind::Pipeline),
+            (RecorderError::AlreadyRunning, ErrorKind::State),
+            (RecorderError::NotRunning, ErrorKind::State),
+        ];
+        for (error, kind) in cases {
+            assert_eq!(error.kind(), kind, "{:?}", error);
+        }
+    }
+}
@@ -0,0 +1,282 @@
+//! Just enough HTTP/1.1 for the metrics and control endpoints: one request
+//! per connection, each connection handled on its own thread so a slow
+//! request doesn't hold up the others.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use crate::error::RecorderError;
+
+/// Largest request body accepted.
+const MAX_BODY: usize = 64 * 1024;
+/// Longest request or header line accepted, in bytes.
+const MAX_LINE: usize = 8 * 1024;
+/// Most headers accepted in a request.
+const MAX_HEADERS: usize = 64;
+/// How long a client may take to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    /// Path without the query string.
+    pub path: String,
+    pub query: Option<String>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new<B: Into<Vec<u8>>>(status: u16, content_type: &'static str, body: B) -> Response {
+        Response { status, content_type, body: body.into() }
+    }
+
+    pub fn text<B: Into<Vec<u8>>>(status: u16, body: B) -> Response {
+        Response::new(status, "text/plain; charset=utf-8", body)
+    }
+
+    pub fn not_found() -> Response {
+        Response::text(404, "Not found\n")
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            413 => "Payload Too Large",
+            500 => "Internal Server Error",
+            _ => "",
+        }
+    }
+}
+
+pub type Handler = Arc<dyn Fn(&Request) -> Response + Send + Sync>;
+
+/// Answers requests on `address` on a background thread: a TCP address
+/// like `127.0.0.1:9100`, or a Unix socket path prefixed with `unix:`.
+pub fn serve(address: &str, handler: Handler) -> Result<(), RecorderError> {
+    let listen_error = |e: io::Error| RecorderError::Listen { address: address.into(), reason: e.to_string() };
+
+    let run: Box<dyn FnOnce() + Send> = match address.strip_prefix("unix:") {
+        Some(path) => {
+            // left behind by a previous run, unless something still listens
+            if fs::symlink_metadata(path).map(|m| m.file_type().is_socket()).unwrap_or(false) {
+                match UnixStream::connect(path) {
+                    Ok(_) => {
+                        return Err(listen_error(io::Error::new(io::ErrorKind::AddrInUse, "already in use")));
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                        fs::remove_file(path).map_err(listen_error)?;
+                    }
+                    Err(e) => return Err(listen_error(e)),
+                }
+            }
+            let listener = UnixListener::bind(path).map_err(listen_error)?;
+            Box::new(move || accept(listener.incoming(), UnixStream::set_read_timeout, handler))
+        }
+        None => {
+            let listener = TcpListener::bind(address).map_err(listen_error)?;
+            Box::new(move || accept(listener.incoming(), TcpStream::set_read_timeout, handler))
+        }
+    };
+
+    thread::Builder::new()
+        .name(format!("http {}", address))
+        .spawn(run)
+        .map_err(listen_error)?;
+
+    Ok(())
+}
+
+fn accept<S>(
+    incoming: impl Iterator<Item = io::Result<S>>,
+    set_read_timeout: fn(&S, Option<Duration>) -> io::Result<()>,
+    handler: Handler,
+) where
+    S: Send + 'static,
+    for<'a> &'a S: Read + Write,
+{
+    for stream in incoming {
+        match stream {
+            Ok(stream) => {
+                let _ = set_read_timeout(&stream, Some(READ_TIMEOUT));
+                let handler = handler.clone();
+                let spawned = thread::Builder::new()
+                    .name(String::from("http connection"))
+                    .spawn(move || handle(&stream, &stream, &handler));
+                if let Err(err) = spawned {
+                    warn!("Cannot handle connection: {}", err);
+                }
+            }
+            Err(err) => warn!("Cannot accept connection: {}", err),
+        }
+    }
+}
+
+/// Reads one request from `input` and writes the answer to `output`.
+pub fn handle<R: Read, W: Write>(input: R, mut output: W, handler: &Handler) {
+    let response = match read_request(&mut BufReader::new(input)) {
+        Ok(request) => {
+            debug!("{} {}", request.method, request.path);
+            handler(&request)
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::InvalidData => Response::text(400, format!("{}\n", e)),
+        Err(_) => return,
+    };
+    let _ = write_response(&mut output, &response);
+}
+
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Request> {
+    let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, reason.to_string());
+
+    let line = read_line(reader)?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return Err(invalid("malformed request line")),
+    };
+
+    let mut content_length = 0;
+    let mut headers = 0;
+    loop {
+        let header = read_line(reader)?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        headers += 1;
+        if headers > MAX_HEADERS {
+            return Err(invalid("too many headers"));
+        }
+        let mut parts = header.splitn(2, ':');
+        if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().map_err(|_| invalid("invalid Content-Length"))?;
+            }
+        }
+    }
+    if content_length > MAX_BODY {
+        return Err(invalid("request body too large"));
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let mut target = target.splitn(2, '?');
+    Ok(Request {
+        method,
+        path: target.next().unwrap_or("/").to_string(),
+        query: target.next().map(String::from),
+        body,
+    })
+}
+
+/// Reads a line of at most [`MAX_LINE`] bytes, empty at the end of `reader`.
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    reader.take(MAX_LINE as u64).read_line(&mut line)?;
+    if line.len() == MAX_LINE && !line.ends_with('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+    }
+    Ok(line)
+}
+
+fn write_response<W: Write>(output: &mut W, response: &Response) -> io::Result<()> {
+    write!(
+        output,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.reason(),
+        response.content_type,
+        response.body.len(),
+    )?;
+    output.write_all(&response.body)?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testutil::exchange;
+
+    /// Answers with the request it got.
+    fn echo() -> Handler {
+        Arc::new(|request: &Request| {
+            let query = request.query.as_deref().unwrap_or("-");
+            let body = String::from_utf8_lossy(&request.body);
+            Response::text(200, format!("{} {} {} {}", request.method, request.path, query, body))
+        })
+    }
+
+    fn status_line(response: &str) -> &str {
+        response.lines().next().unwrap_or("")
+    }
+
+    #[test]
+    fn parses_the_request() {
+        let response = exchange(&echo(), "PUT /bitrate?now=1 HTTP/1.1\r\nHost: x\r\ncontent-length: 4\r\n\r\nbody");
+        assert_eq!(status_line(&response), "HTTP/1.1 200 OK");
+        assert!(response.ends_with("\r\n\r\nPUT /bitrate now=1 body"), "{}", response);
+
+        let response = exchange(&echo(), "GET /status HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\nGET /status - "), "{}", response);
+    }
+
+    #[test]
+    fn writes_the_response() {
+        let mut output = Vec::new();
+        write_response(&mut output, &Response::new(409, "application/json", "{}")).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "HTTP/1.1 409 Conflict\r\nContent-Type: application/json\r\nContent-Length: 2\r\n\
+             Connection: close\r\n\r\n{}",
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_requests() {
+        let status_of = |request: &str| status_line(&exchange(&echo(), request)).to_string();
+
+        assert_eq!(status_of("GET\r\n\r\n"), "HTTP/1.1 400 Bad Request");
+        assert_eq!(status_of("POST / HTTP/1.1\r\nContent-Length: x\r\n\r\n"), "HTTP/1.1 400 Bad Request");
+        let too_large = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY + 1);
+        assert_eq!(status_of(&too_large), "HTTP/1.1 400 Bad Request");
+    }
+
+    #[test]
+    fn limits_lines_and_headers() {
+        let status_of = |request: &str| status_line(&exchange(&echo(), request)).to_string();
+
+        let long_line = format!("GET /{} HTTP/1.1\r\n\r\n", "x".repeat(MAX_LINE));
+        assert_eq!(status_of(&long_line), "HTTP/1.1 400 Bad Request");
+        let long_header = format!("GET / HTTP/1.1\r\nX: {}\r\n\r\n", "x".repeat(MAX_LINE));
+        assert_eq!(status_of(&long_header), "HTTP/1.1 400 Bad Request");
+
+        let headers = |count: usize| format!("GET / HTTP/1.1\r\n{}\r\n", "X: y\r\n".repeat(count));
+        assert_eq!(status_of(&headers(MAX_HEADERS)), "HTTP/1.1 200 OK");
+        assert_eq!(status_of(&headers(MAX_HEADERS + 1)), "HTTP/1.1 400 Bad Request");
+    }
+
+    #[test]
+    fn answers_nothing_to_a_truncated_body() {
+        assert_eq!(exchange(&echo(), "POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort"), "");
+    }
+}
@@ -0,0 +1,33 @@
+pub mod config;
+pub mod container;
+pub mod control;
+pub mod corruption;
+pub mod devices;
+pub mod encoder;
+pub mod error;
+pub mod http;
+pub mod logging;
+pub mod metrics;
+pub mod modes;
+pub mod naming;
+mod pause;
+pub mod pipeline;
+pub mod preflight;
+pub mod recorder;
+pub mod retention;
+pub mod segments;
+pub mod settings;
+pub mod slate;
+pub mod snapshot;
+pub mod source;
+mod split;
+pub mod storage;
+pub mod supervisor;
+#[cfg(test)]
+mod testutil;
+pub mod watchdog;
+
+pub use crate::config::{RecorderConfig, RecorderConfigBuilder};
+pub use crate::error::{ErrorKind, RecorderError};
+pub use crate::recorder::{CameraRecorder, RecorderEvent, RecorderStatus};
+pub use crate::source::VideoSource;
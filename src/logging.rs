@@ -0,0 +1,291 @@
+//! Leveled logging to stderr as text or JSON lines, with GStreamer's own
+//! debug log bridged in.
+//!
+//! Log records are filtered per module with directives like
+//! `info,gst_camera_rs::recorder=debug`: a bare level sets the default, a
+//! `module=level` pair the level of that module and its submodules. The
+//! GStreamer debug log is filtered per category before it gets here, with
+//! the `GST_DEBUG` syntax, e.g. `v4l2src:5,*:2`, and then like any module
+//! with targets like `gstreamer::v4l2src`, e.g. `gstreamer=debug`.
+
+use gstreamer as gst;
+use gstreamer_sys as gst_sys;
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::fmt::Write as _;
+use std::io::{self, Write as _};
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+
+use crate::error::{InvalidSetting, RecorderError};
+
+/// How log records are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = RecorderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(RecorderError::UnknownValue { kind: "log format", value: s.into() }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogConfig {
+    /// Directives filtering records per module.
+    pub filter: String,
+    pub format: LogFormat,
+    /// GStreamer debug categories and levels, `GST_DEBUG` applies if unset.
+    pub gst_debug: Option<String>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            filter: String::from("info"),
+            format: LogFormat::Text,
+            gst_debug: None,
+        }
+    }
+}
+
+/// A record in the JSON format.
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    time: &'a str,
+    level: &'a str,
+    target: &'a str,
+    message: &'a str,
+    #[serde(flatten)]
+    fields: BTreeMap<&'a str, &'a str>,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Installs the logger and bridges the GStreamer debug log into it. Only
+/// the first call has an effect.
+pub fn init(config: &LogConfig) -> Result<(), RecorderError> {
+    let logger = Logger::new(config)?;
+    let max_level = logger.max_level();
+    if LOGGER.set(logger).is_err() {
+        return Ok(());
+    }
+    if let Some(logger) = LOGGER.get() {
+        if log::set_logger(logger).is_ok() {
+            log::set_max_level(max_level);
+        }
+    }
+
+    gst::init()?;
+    if let Some(spec) = &config.gst_debug {
+        gst::debug_set_threshold_from_string(spec, true);
+    }
+    unsafe {
+        gst_sys::gst_debug_remove_log_function(Some(gst_sys::gst_debug_log_default));
+        gst_sys::gst_debug_add_log_function(Some(log_gst_message), ptr::null_mut(), None);
+    }
+
+    Ok(())
+}
+
+struct Logger {
+    /// Most specific module first, the default last.
+    directives: Vec<(String, LevelFilter)>,
+    format: LogFormat,
+}
+
+impl Logger {
+    fn new(config: &LogConfig) -> Result<Logger, RecorderError> {
+        let invalid = |reason: String| InvalidSetting::new("log", reason);
+
+        let mut default = LevelFilter::Info;
+        let mut directives = Vec::new();
+        for directive in config.filter.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let mut parts = directive.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(level), None) if level.parse::<LevelFilter>().is_ok() => {
+                    default = level.parse().unwrap_or(default);
+                }
+                (Some(module), None) => directives.push((module.to_string(), LevelFilter::Trace)),
+                (Some(module), Some(level)) => {
+                    let level = level.parse::<LevelFilter>()
+                        .map_err(|_| invalid(format!("unknown level {} for {}", level, module)))?;
+                    directives.push((module.to_string(), level));
+                }
+                _ => return Err(RecorderError::from(invalid(format!("invalid directive {}", directive)))),
+            }
+        }
+        directives.sort_by_key(|(module, _)| Reverse(module.len()));
+        directives.push((String::new(), default));
+
+        Ok(Logger { directives, format: config.format })
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.directives.iter().map(|(_, level)| *level).max().unwrap_or(LevelFilter::Off)
+    }
+
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.directives.iter()
+            .find(|(module, _)| {
+                module.is_empty()
+                    || target == module
+                    || (target.starts_with(module.as_str()) && target[module.len()..].starts_with("::"))
+            })
+            .map(|(_, level)| *level)
+            .unwrap_or(LevelFilter::Off)
+    }
+
+    fn write(&self, level: Level, target: &str, message: &str, fields: &[(&str, &str)]) {
+        let now = glib::DateTime::new_now_local();
+        let time = format!(
+            "{}.{:03}",
+            now.format("%Y-%m-%dT%H:%M:%S").map(|t| t.to_string()).unwrap_or_default(),
+            now.get_microsecond() / 1000,
+        );
+
+        let mut line = String::new();
+        match self.format {
+            LogFormat::Text => {
+                let _ = write!(line, "{} {:<5} {}: {}", time, level, target, message);
+                for (key, value) in fields {
+                    let _ = write!(line, " {}={}", key, value);
+                }
+            }
+            LogFormat::Json => {
+                let record = JsonRecord {
+                    time: &time,
+                    level: level.as_str(),
+                    target,
+                    message,
+                    fields: fields.iter().cloned().collect(),
+                };
+                line = serde_json::to_string(&record).unwrap_or_default();
+            }
+        }
+        line.push('\n');
+
+        let _ = io::stderr().write_all(line.as_bytes());
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        self.write(record.level(), record.target(), &record.args().to_string(), &[]);
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+/// Receives every GStreamer debug message passing the category thresholds.
+unsafe extern "C" fn log_gst_message(
+    category: *mut gst_sys::GstDebugCategory,
+    level: gst_sys::GstDebugLevel,
+    file: *const c_char,
+    _function: *const c_char,
+    line: c_int,
+    object: *mut gobject_sys::GObject,
+    message: *mut gst_sys::GstDebugMessage,
+    _user_data: glib_sys::gpointer,
+) {
+    let logger = match LOGGER.get() {
+        Some(logger) => logger,
+        None => return,
+    };
+    let level = match level {
+        gst_sys::GST_LEVEL_ERROR => Level::Error,
+        gst_sys::GST_LEVEL_WARNING | gst_sys::GST_LEVEL_FIXME => Level::Warn,
+        gst_sys::GST_LEVEL_INFO => Level::Info,
+        gst_sys::GST_LEVEL_DEBUG => Level::Debug,
+        _ => Level::Trace,
+    };
+
+    let text = |s: *const c_char| if s.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(s).to_string_lossy().into_owned()
+    };
+    let target = format!("gstreamer::{}", text(gst_sys::gst_debug_category_get_name(category)));
+    if level > logger.level_for(&target) {
+        return;
+    }
+    let message = text(gst_sys::gst_debug_message_get(message));
+    let location = format!("{}:{}", text(file), line);
+
+    // the name is read without locking, like the default log function does
+    let is_gst_object = !object.is_null()
+        && gobject_sys::g_type_check_instance_is_a(
+            object as *mut gobject_sys::GTypeInstance,
+            gst_sys::gst_object_get_type(),
+        ) != glib_sys::GFALSE;
+    if is_gst_object {
+        let name = text((*(object as *mut gst_sys::GstObject)).name);
+        logger.write(level, &target, &message, &[("object", &name), ("location", &location)]);
+    } else {
+        logger.write(level, &target, &message, &[("location", &location)]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logger(filter: &str) -> Result<Logger, RecorderError> {
+        Logger::new(&LogConfig { filter: filter.into(), ..LogConfig::default() })
+    }
+
+    #[test]
+    fn picks_the_most_specific_module() {
+        let logger = logger("warn, gst_camera_rs=info,gst_camera_rs::recorder=debug").unwrap();
+        assert_eq!(logger.level_for("gst_camera_rs::recorder"), LevelFilter::Debug);
+        assert_eq!(logger.level_for("gst_camera_rs::recorder::bus"), LevelFilter::Debug);
+        assert_eq!(logger.level_for("gst_camera_rs::recorders"), LevelFilter::Info);
+        assert_eq!(logger.level_for("gst_camera_rs"), LevelFilter::Info);
+        assert_eq!(logger.level_for("gst_camera_rsx"), LevelFilter::Warn);
+        assert_eq!(logger.level_for("gstreamer::v4l2src"), LevelFilter::Warn);
+        assert_eq!(logger.max_level(), LevelFilter::Debug);
+    }
+
+    #[test]
+    fn parses_directives() {
+        let logger = logger("gstreamer,error").unwrap();
+        assert_eq!(logger.level_for("gstreamer::v4l2src"), LevelFilter::Trace);
+        assert_eq!(logger.level_for("gst_camera_rs"), LevelFilter::Error);
+
+        assert_eq!(self::logger("").unwrap().level_for("gst_camera_rs"), LevelFilter::Info);
+        assert_eq!(self::logger("off").unwrap().max_level(), LevelFilter::Off);
+
+        match self::logger("gst_camera_rs=loud") {
+            Err(RecorderError::Config(err)) => {
+                assert_eq!(err.key, "log");
+                assert!(err.reason.contains("loud"), "{}", err);
+            }
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+    }
+}
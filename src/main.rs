@@ -1,218 +1,646 @@
-use gstreamer as gst;
-use gst::prelude::*;
-
-use std::env;
 use std::error::Error as StdError;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use log::{debug, error, info, warn};
+use structopt::StructOpt;
+
+use gst_camera_rs::container::Container;
+use gst_camera_rs::encoder::Encoder;
+use gst_camera_rs::logging::{self, LogConfig, LogFormat};
+use gst_camera_rs::modes::{ModeConstraints, PixelFormat};
+use gst_camera_rs::settings::{Settings, SettingsLoader};
+use gst_camera_rs::{control, devices, http, metrics, preflight, segments, snapshot};
+use gst_camera_rs::{CameraRecorder, RecorderConfig, RecorderEvent, VideoSource};
+
+const SIGINT: i32 = 2;
+const SIGTERM: i32 = 15;
+
+type Error = Box<dyn StdError>;
+
+fn parse_resolution(s: &str) -> Result<(i32, i32), String> {
+    let mut parts = s.splitn(2, 'x');
+    match (parts.next().map(str::parse), parts.next().map(str::parse)) {
+        (Some(Ok(width)), Some(Ok(height))) => Ok((width, height)),
+        _ => Err(format!("Invalid resolution {}, expected <width>x<height>", s)),
+    }
+}
+
+fn parse_override(s: &str) -> Result<(String, String), String> {
+    let mut parts = s.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(key), Some(value)) if !key.is_empty() => Ok((key.into(), value.into())),
+        _ => Err(format!("Invalid override {}, expected <key>=<value>", s)),
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct SettingsOpts {
+    /// TOML settings file
+    #[structopt(short, long)]
+    config: Option<PathBuf>,
+
+    /// Camera section of the settings file to use
+    #[structopt(long)]
+    camera: Option<String>,
+
+    /// Override any setting, e.g. --set encoder.profile=main
+    #[structopt(long = "set", number_of_values = 1, parse(try_from_str = parse_override))]
+    overrides: Vec<(String, String)>,
+}
+
+#[derive(Debug, StructOpt)]
+struct CaptureOpts {
+    /// Video source: a /dev/video* device, serial:<serial>, test[:<pattern>],
+    /// a URI or a file
+    source: Option<VideoSource>,
+
+    #[structopt(flatten)]
+    mode: ModeOpts,
+}
+
+#[derive(Debug, StructOpt)]
+struct ModeOpts {
+    /// Capture resolution as <width>x<height>
+    #[structopt(short, long, parse(try_from_str = parse_resolution))]
+    resolution: Option<(i32, i32)>,
+
+    /// Capture framerate in frames per second
+    #[structopt(short, long)]
+    framerate: Option<i32>,
+
+    /// Largest acceptable resolution as <width>x<height>
+    #[structopt(long, parse(try_from_str = parse_resolution))]
+    max_resolution: Option<(i32, i32)>,
+
+    /// Lowest acceptable framerate in frames per second
+    #[structopt(long)]
+    min_framerate: Option<i32>,
 
-use failure::Error;
-use failure_derive::Fail;
+    /// Pixel format to prefer, e.g. mjpeg or yuy2
+    #[structopt(long)]
+    prefer_format: Option<PixelFormat>,
+}
+
+#[derive(Debug, StructOpt)]
+struct EncodeOpts {
+    /// Video encoder: x264, x265, vp8, vp9, av1 or mjpeg
+    #[structopt(short, long)]
+    encoder: Option<Encoder>,
+
+    /// Encoder bitrate in kbit/s
+    #[structopt(short, long)]
+    bitrate: Option<u32>,
+
+    /// Maximum number of frames between two keyframes
+    #[structopt(short, long)]
+    keyframe_interval: Option<u32>,
+
+    /// Duration of a single segment in seconds
+    #[structopt(short, long)]
+    segment_duration: Option<u64>,
+
+    /// Split on wall-clock multiples of the segment duration, e.g. every
+    /// full minute
+    #[structopt(long)]
+    align_splits: bool,
+
+    /// Record the camera's MJPEG without re-encoding
+    #[structopt(long)]
+    passthrough: bool,
+
+    /// Segment container: mp4, fragmentedmp4, matroska, mpegts or avi
+    #[structopt(long)]
+    container: Option<Container>,
+}
 
-#[derive(Debug, Fail)]
-#[fail(display = "Usage: {} <device> <location>", _0)]
-struct UsageError(String);
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Records a V4L2 camera into segmented video files")]
+struct Opts {
+    /// Log filter, e.g. info,gst_camera_rs::recorder=debug
+    #[structopt(long, default_value = "info")]
+    log: String,
 
-#[derive(Debug, Fail)]
-#[fail(display = "Missing element {}", _0)]
-struct MissingElement(&'static str);
+    /// Log format: text or json
+    #[structopt(long, default_value = "text")]
+    log_format: LogFormat,
 
-#[derive(Debug, Fail)]
-#[fail(display = "Bus watch error")]
-struct WatchError;
+    /// GStreamer debug categories to log, e.g. v4l2src:5,*:2, further
+    /// filtered by --log as gstreamer::<category>
+    #[structopt(long)]
+    gst_debug: Option<String>,
 
-#[derive(Debug, Fail)]
-#[fail(display = "Received error from {}: {} (debug: {:?})", src, error, debug)]
-struct ErrorMessage {
-    src: String,
-    error: String,
-    debug: Option<String>,
-    #[cause]
-    cause: glib::Error,
+    #[structopt(subcommand)]
+    command: Command,
 }
 
-fn make_element<'a, P: Into<Option<&'a str>>>(
-    factory_name: &'static str,
-    element_name: P,
-) -> Result<gst::Element, Error> {
-    match gst::ElementFactory::make(factory_name, element_name.into()) {
-        Some(elem) => Ok(elem),
-        None => Err(Error::from(MissingElement(factory_name))),
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Record the camera into segmented files
+    Record {
+        #[structopt(flatten)]
+        capture: CaptureOpts,
+
+        /// Output location pattern, e.g. video%05d.mp4 or a template like
+        /// {camera}-{time:%Y%m%d-%H%M%S}-{seq}.mkv
+        location: Option<String>,
+
+        /// Rebuild the pipeline with backoff after errors instead of exiting
+        #[structopt(long)]
+        restart: bool,
+
+        /// Serve Prometheus metrics on /metrics at this address, e.g.
+        /// 127.0.0.1:9100 or unix:/run/camera-metrics.sock
+        #[structopt(long)]
+        metrics: Option<String>,
+
+        /// Serve the control API at this address, e.g. 127.0.0.1:8080 or
+        /// unix:/run/camera.sock, and keep running once recording stops
+        #[structopt(long)]
+        control: Option<String>,
+
+        #[structopt(flatten)]
+        encode: EncodeOpts,
+
+        #[structopt(flatten)]
+        settings: SettingsOpts,
+    },
+    /// Check that every element and the camera needed for recording are
+    /// available
+    Doctor {
+        #[structopt(flatten)]
+        capture: CaptureOpts,
+
+        /// Output location pattern, as for record, the output settings are
+        /// only checked along with one
+        location: Option<String>,
+
+        #[structopt(flatten)]
+        encode: EncodeOpts,
+
+        #[structopt(flatten)]
+        settings: SettingsOpts,
+    },
+    /// Print every mode supported by a device and the one that would be used
+    Probe {
+        /// Video device, e.g. /dev/video0
+        device: String,
+
+        #[structopt(flatten)]
+        mode: ModeOpts,
+    },
+    /// Save a single JPEG frame from the camera
+    Snapshot {
+        #[structopt(flatten)]
+        capture: CaptureOpts,
+
+        /// Output JPEG file
+        #[structopt(short, long, default_value = "snapshot.jpg")]
+        output: PathBuf,
+
+        #[structopt(flatten)]
+        settings: SettingsOpts,
+    },
+    /// List available video capture devices
+    List,
+    /// Check that recorded segments can be decoded
+    Verify {
+        /// Segment files to check
+        #[structopt(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Concatenate recorded segments into a single MP4 file
+    Export {
+        /// Glob matching the segments, e.g. 'video*.mp4'
+        pattern: String,
+
+        /// Output file, its extension picks the container, e.g. .mp4 or .mkv
+        output: PathBuf,
+    },
+}
+
+impl SettingsOpts {
+    /// Settings file first, then the environment. Flags are applied by the
+    /// caller before [`SettingsOpts::finish`].
+    fn loader(&self) -> Result<SettingsLoader, Error> {
+        let mut loader = SettingsLoader::new();
+        if let Some(path) = &self.config {
+            loader = loader.file(path, self.camera.as_deref())?;
+        }
+        Ok(loader.env())
+    }
+
+    fn finish(&self, mut loader: SettingsLoader) -> Result<Settings, Error> {
+        for (key, value) in &self.overrides {
+            loader = loader.set_str(key, value);
+        }
+        Ok(loader.settings()?)
     }
 }
 
-// TODO refactor expect into error type
+impl CaptureOpts {
+    fn apply(&self, mut loader: SettingsLoader) -> SettingsLoader {
+        loader = match &self.source {
+            Some(VideoSource::V4l2 { device }) => loader
+                .set("source.kind", "v4l2")
+                .set("source.device", device.as_str()),
+            Some(VideoSource::Test { pattern }) => loader
+                .set("source.kind", "test")
+                .set("source.pattern", pattern.as_str()),
+            Some(VideoSource::File { path }) => loader
+                .set("source.kind", "file")
+                .set("source.path", path.display().to_string()),
+            Some(VideoSource::Uri { uri }) => loader
+                .set("source.kind", "uri")
+                .set("source.uri", uri.as_str()),
+            None => loader,
+        };
+        self.mode.apply(loader)
+    }
+}
 
-fn run() -> Result<(), Error> {
-    // region parse args
-    let args = env::args().collect::<Vec<String>>();
-
-    if args.len() != 3 {
-        return Err(Error::from(UsageError(args[0].clone())));
-    }
-
-    let device = args[1].clone();
-    let location = args[2].clone();
-    println!("device: {} location: {}", &device, &location);
-    // endregion
-
-    // init gstreamer
-    gst::init()?;
-
-    // init loop
-    let main_loop = glib::MainLoop::new(None, false);
-
-    // create pipeline
-    let pipeline = gst::Pipeline::new("camera-recorder");
-
-    // region create elements
-    // video source
-    let v4l2src: gst::Element = gst::ElementFactory::make("v4l2src", "v4l2src")
-        .ok_or(MissingElement("v4l2src"))?;
-    v4l2src.set_property("device", &device)?;
-
-    // video filter
-    let video_filter = make_element("capsfilter", None)?;
-    let video_caps = gst::Caps::builder("image/jpeg")
-        .field("width", &2592i32)
-        .field("height", &1944i32)
-        .build();
-    video_filter.set_property("caps", &video_caps)?;
-
-    // jpeg decoder
-    let jpegdec = gst::ElementFactory::make("jpegdec", "jpegdec")
-        .ok_or(MissingElement("jpegdec"))?;
-
-    // encode queue
-    let encode_queue = gst::ElementFactory::make("queue", "encode_queue")
-        .ok_or(MissingElement("encode_queue"))?;
-
-    // x264 encoder
-    let x264enc = gst::ElementFactory::make("x264enc", "x264enc")
-        .ok_or(MissingElement("x264enc"))?;
-    x264enc.set_property("key-int-max", &10u32.to_value())?;
-
-    // h264 filter
-    let h264_filter = gst::ElementFactory::make("capsfilter", "h264_filter")
-        .ok_or(MissingElement("h264_filter"))?;
-    let encode_caps = gst::Caps::builder("video/x-h264")
-        .field("profile", &("high"))
-        .build();
-    h264_filter.set_property("caps", &encode_caps)?;
-
-    // h264 parser
-    let h264parse = gst::ElementFactory::make("h264parse", "h264parse")
-        .ok_or(MissingElement("h264parse"))?;
-
-    // sink
-    let splitmuxsink = gst::ElementFactory::make("splitmuxsink", "splitmuxsink")
-        .ok_or(MissingElement("splitmuxsink"))?;
-    splitmuxsink.set_property("location", &location)?;
-    splitmuxsink.set_property("max-size-time", &10000000000u64.to_value())?;
-    splitmuxsink.set_property("send-keyframe-requests", &true.to_value())?;
-    // endregion
-
-    // region set up the pipeline
-    // add elements
-    pipeline.add_many(&[
-        &v4l2src,
-        &video_filter,
-        &jpegdec,
-        &encode_queue,
-        &x264enc,
-        &h264_filter,
-        &h264parse,
-        &splitmuxsink,
-    ])?;
-
-    // link elements
-    gst::Element::link_many(&[
-        &v4l2src,
-        &video_filter,
-        &jpegdec,
-        &encode_queue,
-        &x264enc,
-        &h264_filter,
-        &h264parse,
-        &splitmuxsink,
-    ])?;
-    // endregion
-
-    // region add message handler
-    let bus: gst::Bus = pipeline.get_bus()
-        .expect("Pipeline doesn't have a bus (shouldn't happen)!");
-    let loop_clone = main_loop.clone();
-    let bus_watch_id = bus.add_watch(move |_, msg| {
-        use gst::MessageView;
-
-        println!("Got {:?} message", msg);
-
-        match msg.view() {
-            MessageView::Eos(..) => {
-                println!("End of stream.");
-                loop_clone.quit();
-            }
-            MessageView::Error(err) => {
-                let error_msg = ErrorMessage {
-                    src: msg
-                        .get_src()
-                        .map(|s| String::from(s.get_path_string()))
-                        .unwrap_or_else(|| String::from("None")),
-                    error: err.get_error().description().into(),
-                    debug: Some(err.get_debug().unwrap().to_string()),
-                    cause: err.get_error(),
-                };
-
-                eprintln!("Error: {}", error_msg);
-                loop_clone.quit();
-            }
-            MessageView::Warning(w) => {
-                let error_msg = ErrorMessage {
-                    src: msg
-                        .get_src()
-                        .map(|s| String::from(s.get_path_string()))
-                        .unwrap_or_else(|| String::from("None")),
-                    error: w.get_error().description().into(),
-                    debug: Some(w.get_debug().unwrap().to_string()),
-                    cause: w.get_error(),
-                };
-
-                eprintln!("Warning: {}", error_msg);
-            }
-            MessageView::StateChanged(s) => {
-                println!(
+impl ModeOpts {
+    fn apply(&self, mut loader: SettingsLoader) -> SettingsLoader {
+        if let Some((width, height)) = self.resolution {
+            loader = loader.set("video.width", i64::from(width)).set("video.height", i64::from(height));
+        }
+        if let Some(framerate) = self.framerate {
+            loader = loader.set("video.framerate", i64::from(framerate));
+        }
+        if let Some((width, height)) = self.max_resolution {
+            loader = loader.set("video.max_width", i64::from(width)).set("video.max_height", i64::from(height));
+        }
+        if let Some(framerate) = self.min_framerate {
+            loader = loader.set("video.min_framerate", i64::from(framerate));
+        }
+        if let Some(format) = &self.prefer_format {
+            loader = loader.set("video.prefer_format", format.to_string());
+        }
+        loader
+    }
+
+    fn constraints(&self) -> ModeConstraints {
+        ModeConstraints {
+            width: self.resolution.map(|(width, _)| width),
+            height: self.resolution.map(|(_, height)| height),
+            framerate: self.framerate,
+            max_width: self.max_resolution.map(|(width, _)| width),
+            max_height: self.max_resolution.map(|(_, height)| height),
+            min_framerate: self.min_framerate,
+            format: None,
+            // same default as the recorder
+            prefer_format: self.prefer_format.clone().or(Some(PixelFormat::Mjpeg)),
+        }
+    }
+}
+
+impl EncodeOpts {
+    fn apply(&self, mut loader: SettingsLoader) -> SettingsLoader {
+        if let Some(encoder) = self.encoder {
+            loader = loader.set("encoder.kind", encoder.to_string());
+        }
+        if let Some(bitrate) = self.bitrate {
+            loader = loader.set("encoder.bitrate", i64::from(bitrate));
+        }
+        if let Some(keyframe_interval) = self.keyframe_interval {
+            loader = loader.set("encoder.key_int_max", i64::from(keyframe_interval));
+        }
+        if let Some(segment_duration) = self.segment_duration {
+            loader = loader.set("output.segment_duration", segment_duration as i64);
+        }
+        if self.align_splits {
+            loader = loader.set("output.align_splits", true);
+        }
+        if self.passthrough {
+            loader = loader.set("encoder.passthrough", true);
+        }
+        if let Some(container) = self.container {
+            loader = loader.set("output.container", container.to_string());
+        }
+        loader
+    }
+}
+
+/// Stops `recorder` gracefully on SIGINT or SIGTERM, a second signal exits
+/// right away.
+fn stop_on_signals(recorder: &Arc<CameraRecorder>) -> Result<Arc<AtomicBool>, Error> {
+    let context = glib::MainContext::new();
+    let main_loop = glib::MainLoop::new(Some(&context), false);
+    let signalled = Arc::new(AtomicBool::new(false));
+    let result = signalled.clone();
+
+    for &signum in &[SIGINT, SIGTERM] {
+        let recorder = Arc::downgrade(recorder);
+        let signalled = signalled.clone();
+        glib::unix_signal_source_new(signum, None, glib::PRIORITY_DEFAULT, move || {
+            if signalled.swap(true, Ordering::SeqCst) {
+                warn!("Interrupted again, exiting without finalizing");
+                process::exit(128 + signum);
+            }
+            info!("Interrupted, finalizing the current segment...");
+            if let Some(recorder) = recorder.upgrade() {
+                // already stopped through the control API, nothing to finalize
+                if recorder.request_stop().is_err() {
+                    process::exit(0);
+                }
+            }
+            glib::Continue(true)
+        })
+            .attach(Some(&context));
+    }
+
+    thread::Builder::new()
+        .name(String::from("signals"))
+        .spawn(move || {
+            context.push_thread_default();
+            main_loop.run();
+        })?;
+
+    Ok(result)
+}
+
+fn record(config: RecorderConfig, metrics_address: Option<&str>, control_address: Option<&str>) -> Result<(), Error> {
+    info!("source: {} location: {}", &config.source, &config.location);
+
+    let recorder = Arc::new(CameraRecorder::new(config));
+    let events = recorder.subscribe();
+    let signalled = stop_on_signals(&recorder)?;
+
+    if let Some(address) = metrics_address {
+        http::serve(address, metrics::handler(vec![recorder.metrics()]))?;
+        info!("Serving metrics on {}", address);
+    }
+    if let Some(address) = control_address {
+        http::serve(address, control::handler(recorder.clone()))?;
+        info!("Serving the control API on {}", address);
+    }
+
+    // start playing
+    info!("Now playing");
+    recorder.start()?;
+
+    // main loop
+    info!("Running...");
+    let mut result = Ok(());
+    for event in events {
+        match event {
+            RecorderEvent::Eos => info!("End of stream."),
+            RecorderEvent::Error(err) => {
+                error!("{}", err);
+                result = Err(Error::from(err));
+            }
+            RecorderEvent::Warning(w) => warn!("{}", w),
+            RecorderEvent::SegmentClosed { location } => info!("Closed segment {}", location),
+            RecorderEvent::SegmentDeleted(path) => info!("Deleted old segment {}", path.display()),
+            RecorderEvent::RetentionFailed(err) => error!("Retention failed: {}", err),
+            RecorderEvent::LowSpace { free, action } => {
+                warn!("Low disk space ({} bytes free), {}", free, action)
+            }
+            RecorderEvent::SpaceRecovered { free, action } => {
+                info!("Disk space recovered ({} bytes free), no longer {}", free, action)
+            }
+            RecorderEvent::DiskFull { free } => error!("Disk full ({} bytes free)", free),
+            RecorderEvent::StorageFailed(err) => error!("Storage check failed: {}", err),
+            RecorderEvent::PipelineFailed(err) => {
+                error!("Pipeline failed: {}", err);
+                result = Err(Error::from(err));
+            }
+            RecorderEvent::Restarting { attempt, delay } => {
+                warn!("Restarting in {:?} (attempt {})", delay, attempt);
+                result = Ok(());
+            }
+            RecorderEvent::RestartLimitReached => error!("Too many restarts, giving up"),
+            RecorderEvent::DeviceOffline { device } => {
+                warn!("Camera {} is offline, waiting for it", device);
+                result = Ok(());
+            }
+            RecorderEvent::DeviceOnline { device } => info!("Camera {} is back online", device),
+            RecorderEvent::SignalLost => warn!("Signal lost, recording the slate"),
+            RecorderEvent::SignalRestored => info!("Signal restored"),
+            RecorderEvent::FrameStall { since } => warn!("No frames from the source for {:?}", since),
+            RecorderEvent::FramesResumed => info!("Frames resumed"),
+            RecorderEvent::Paused => info!("Recording paused"),
+            RecorderEvent::Resumed => info!("Recording resumed"),
+            RecorderEvent::CorruptFrames { dropped } => warn!("Dropped {} corrupt frames", dropped),
+            RecorderEvent::TooManyCorruptFrames { corrupt, frames } => {
+                error!("{} of {} frames were corrupt", corrupt, frames);
+                result = Err(Error::from("too many corrupt frames"));
+            }
+            RecorderEvent::ShutdownTimedOut => error!("Timed out finalizing the last segment"),
+            RecorderEvent::StateChanged { src, old, current, pending } => {
+                debug!(
                     "State changed from {:?}: {:?} -> {:?} ({:?})",
-                    s.get_src().map(|s| s.get_path_string()),
-                    s.get_old(),
-                    s.get_current(),
-                    s.get_pending()
+                    src, old, current, pending
                 );
             }
+            // the control API may start it again
+            RecorderEvent::Stopped if control_address.is_none() || signalled.load(Ordering::SeqCst) => break,
+            RecorderEvent::Stopped => info!("Recording stopped"),
             _ => (),
         }
+    }
 
-        glib::Continue(true)
-    })
-        .ok_or(WatchError)?;
-    // endregion
+    // clean up
+    info!("Stopping...");
+    recorder.wait();
 
-    // start playing
-    println!("Now playing");
-    pipeline.set_state(gst::State::Playing)?;
+    result
+}
 
-    // main loop
-    println!("Running...");
-    main_loop.run();
+/// Settings to check, the output settings only along with a location.
+fn doctor_config(
+    capture: &CaptureOpts,
+    location: Option<String>,
+    encode: &EncodeOpts,
+    settings: &SettingsOpts,
+) -> Result<RecorderConfig, Error> {
+    let mut loader = encode.apply(capture.apply(settings.loader()?));
+    if let Some(location) = location {
+        loader = loader.set("output.location", location);
+    }
+    let settings = settings.finish(loader)?;
+    if settings.output.location.is_some() {
+        Ok(settings.into_config()?)
+    } else {
+        Ok(settings.into_capture_config()?)
+    }
+}
 
-    // clean up
-    println!("Stopping...");
-    pipeline.set_state(gst::State::Null)?;
-    glib::source_remove(bus_watch_id);
+/// Settings of the camera to take a snapshot of, the output settings
+/// don't apply to it.
+fn snapshot_config(capture: &CaptureOpts, settings: &SettingsOpts) -> Result<RecorderConfig, Error> {
+    let loader = capture.apply(settings.loader()?);
+    Ok(settings.finish(loader)?.into_capture_config()?)
+}
+
+fn doctor(config: &RecorderConfig) -> Result<(), Error> {
+    let report = preflight::check(config)?;
+
+    if report.missing.is_empty() {
+        println!("elements: ok");
+    }
+    for missing in &report.missing {
+        println!("missing element: {}", missing);
+    }
 
+    match (config.source.device(), &report.device) {
+        (Some(device), Some(Ok(mode))) => println!("device {}: ok, recording {}", device, mode),
+        (Some(device), Some(Err(err))) => println!("device {}: {}", device, err),
+        _ => (),
+    }
+
+    if !report.is_ok() {
+        return Err(Error::from("Preflight checks failed"));
+    }
     Ok(())
 }
 
+fn run() -> Result<(), Error> {
+    let opts = Opts::from_args();
+    logging::init(&LogConfig {
+        filter: opts.log,
+        format: opts.log_format,
+        gst_debug: opts.gst_debug,
+    })?;
+
+    match opts.command {
+        Command::Record { capture, location, restart, metrics, control, encode, settings } => {
+            let mut loader = encode.apply(capture.apply(settings.loader()?));
+            if let Some(location) = location {
+                loader = loader.set("output.location", location);
+            }
+            if restart {
+                loader = loader.set("restart.enabled", true);
+            }
+            record(settings.finish(loader)?.into_config()?, metrics.as_deref(), control.as_deref())
+        }
+        Command::Doctor { capture, location, encode, settings } => {
+            doctor(&doctor_config(&capture, location, &encode, &settings)?)
+        }
+        Command::Probe { device, mode } => {
+            let modes = devices::probe_modes(&device)?;
+            let selected = mode.constraints().select(&modes);
+            for m in &modes {
+                let marker = if Some(m) == selected { "*" } else { " " };
+                println!("{} {}", marker, m);
+            }
+            if selected.is_none() {
+                println!("No mode matches {}", mode.constraints());
+            }
+            Ok(())
+        }
+        Command::Snapshot { capture, output, settings } => {
+            Ok(snapshot::snapshot(&snapshot_config(&capture, &settings)?, &output)?)
+        }
+        Command::List => {
+            for device in devices::list()? {
+                println!(
+                    "{}\t{}\t{}",
+                    device.path.as_deref().unwrap_or("-"),
+                    device.serial.as_deref().unwrap_or("-"),
+                    device.name
+                );
+            }
+            Ok(())
+        }
+        Command::Verify { files } => {
+            let mut failed = false;
+            for file in &files {
+                match segments::verify(file) {
+                    Ok(()) => println!("{}: ok", file.display()),
+                    Err(e) => {
+                        println!("{}: {}", file.display(), e);
+                        failed = true;
+                    }
+                }
+            }
+            if failed {
+                return Err(Error::from("Some segments failed verification"));
+            }
+            Ok(())
+        }
+        Command::Export { pattern, output } => Ok(segments::export(&pattern, &output)?),
+    }
+}
+
 fn main() {
-    match run() {
-        Ok(r) => r,
-        Err(e) => eprintln!("Error! {}", e)
+    if let Err(e) = run() {
+        eprintln!("Error! {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+
+    /// The example settings file of the settings module doc.
+    fn settings_example() -> String {
+        include_str!("settings.rs")
+            .lines()
+            .filter_map(|line| line.strip_prefix("//!"))
+            .skip_while(|line| line.trim() != "```toml")
+            .skip(1)
+            .take_while(|line| line.trim() != "```")
+            .map(|line| line.strip_prefix(' ').unwrap_or(line))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn snapshots_cameras_recording_into_other_containers() {
+        let path = env::temp_dir().join(format!("gst-camera-rs-snapshot-{}.toml", process::id()));
+        fs::write(&path, settings_example()).unwrap();
+
+        let args = ["gst-camera-rs", "snapshot", "--config", path.to_str().unwrap(), "--camera", "front"];
+        let config = match Opts::from_iter(&args).command {
+            Command::Snapshot { capture, settings, .. } => snapshot_config(&capture, &settings).unwrap(),
+            command => panic!("{:?}", command),
+        };
+        assert_eq!(config.source, VideoSource::V4l2 { device: String::from("/dev/video0") });
+
+        let _ = fs::remove_file(&path);
+    }
+
+    fn doctor_config_of(args: &[&str]) -> Result<RecorderConfig, Error> {
+        let args = ["gst-camera-rs", "doctor"].iter().chain(args);
+        match Opts::from_iter(args).command {
+            Command::Doctor { capture, location, encode, settings } => {
+                doctor_config(&capture, location, &encode, &settings)
+            }
+            command => panic!("{:?}", command),
+        }
+    }
+
+    #[test]
+    fn checks_the_output_only_along_with_a_location() {
+        let config = doctor_config_of(&["/dev/video0"]).unwrap();
+        assert_eq!(config.source, VideoSource::V4l2 { device: String::from("/dev/video0") });
+
+        assert!(doctor_config_of(&["/dev/video0", "video%05d.mkv"]).is_ok());
+        assert!(doctor_config_of(&["/dev/video0", "video%05d.avi", "--encoder", "vp9"]).is_err());
+    }
+
+    #[test]
+    fn parses_resolutions() {
+        assert_eq!(parse_resolution("1280x720"), Ok((1280, 720)));
+        for bad in &["1280", "x720", "1280x", "1280X720", "1280x720x2", "widexhigh", ""] {
+            assert!(parse_resolution(bad).is_err(), "{} was accepted", bad);
+        }
+    }
+
+    #[test]
+    fn parses_overrides() {
+        assert_eq!(parse_override("encoder.profile=main"), Ok((String::from("encoder.profile"), String::from("main"))));
+        assert_eq!(parse_override("slate.text=a=b"), Ok((String::from("slate.text"), String::from("a=b"))));
+        assert_eq!(parse_override("slate.text="), Ok((String::from("slate.text"), String::new())));
+        assert!(parse_override("key").is_err());
+        assert!(parse_override("=main").is_err());
     }
 }
@@ -0,0 +1,326 @@
+//! Recorder health in the Prometheus text format, served on `/metrics`.
+
+use gstreamer as gst;
+use gst::prelude::*;
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::encoder;
+use crate::http::{Handler, Request, Response};
+
+/// Counters and gauges of one recorder, kept across pipeline restarts.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    camera: String,
+    frames_in: AtomicU64,
+    frames_out: AtomicU64,
+    /// f64 bits
+    fps_in: AtomicU64,
+    /// f64 bits
+    fps_out: AtomicU64,
+    dropped_qos: AtomicU64,
+    dropped_corrupt: AtomicU64,
+    /// bit/s, 0 without an encoder
+    encoder_bitrate: AtomicU64,
+    queue_buffers: AtomicU64,
+    queue_bytes: AtomicU64,
+    /// nanoseconds
+    queue_time: AtomicU64,
+    /// f64 bits
+    queue_fill: AtomicU64,
+    segments: AtomicU64,
+    bytes: AtomicU64,
+    restarts: AtomicU64,
+    errors: AtomicU64,
+    /// Frames each element reported as dropped in its last QoS message.
+    qos_dropped: Mutex<HashMap<String, u64>>,
+}
+
+impl Metrics {
+    pub fn new<S: Into<String>>(camera: S) -> Metrics {
+        Metrics {
+            camera: camera.into(),
+            ..Metrics::default()
+        }
+    }
+
+    /// Counts the frames coming out of the source and going into the sink
+    /// of `pipeline`.
+    pub(crate) fn attach(self: &Arc<Self>, pipeline: &gst::Pipeline) {
+        let pads = [
+            (pipeline.get_by_name("source").and_then(|e| e.get_static_pad("src")), true),
+            (pipeline.get_by_name("splitmuxsink").and_then(|e| e.get_static_pad("video")), false),
+        ];
+        for (pad, is_input) in pads.iter() {
+            if let Some(pad) = pad {
+                let metrics = self.clone();
+                let is_input = *is_input;
+                pad.add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+                    let frames = if is_input { &metrics.frames_in } else { &metrics.frames_out };
+                    frames.fetch_add(1, Ordering::Relaxed);
+                    gst::PadProbeReturn::Ok
+                });
+            }
+        }
+    }
+
+    /// `dropped` is the running total `element` reports in QoS messages.
+    pub(crate) fn qos(&self, element: &str, dropped: u64) {
+        let mut qos_dropped = self.qos_dropped.lock().unwrap();
+        let last = qos_dropped.insert(element.to_string(), dropped).unwrap_or(0);
+        // the element restarted counting, along with the pipeline
+        let new = if dropped >= last { dropped - last } else { dropped };
+        self.dropped_qos.fetch_add(new, Ordering::Relaxed);
+    }
+
+    pub(crate) fn corrupt_frame(&self) {
+        self.dropped_corrupt.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn segment_written(&self, bytes: u64) {
+        self.segments.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn restarted(&self) {
+        self.restarts.fetch_add(1, Ordering::Relaxed);
+        self.qos_dropped.lock().unwrap().clear();
+    }
+
+    pub(crate) fn error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Appends every metric in the Prometheus text format, labelled with
+    /// the camera name.
+    fn render(metrics: &[Arc<Metrics>]) -> String {
+        let load = |value: &AtomicU64| value.load(Ordering::Relaxed) as f64;
+        let load_f64 = |value: &AtomicU64| f64::from_bits(value.load(Ordering::Relaxed));
+
+        let mut out = String::new();
+        let mut family = |name: &str, kind: &str, help: &str, value: &dyn Fn(&Metrics) -> Vec<(String, f64)>| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for m in metrics {
+                for (labels, value) in value(m) {
+                    let _ = writeln!(out, "{}{{camera=\"{}\"{}}} {}", name, escape(&m.camera), labels, value);
+                }
+            }
+        };
+        let one = |value: f64| vec![(String::new(), value)];
+
+        family("gst_camera_frames_in_total", "counter", "Frames delivered by the source.", &|m| {
+            one(load(&m.frames_in))
+        });
+        family("gst_camera_frames_out_total", "counter", "Frames written to the muxer.", &|m| {
+            one(load(&m.frames_out))
+        });
+        family("gst_camera_fps_in", "gauge", "Frames per second delivered by the source.", &|m| {
+            one(load_f64(&m.fps_in))
+        });
+        family("gst_camera_fps_out", "gauge", "Frames per second written to the muxer.", &|m| {
+            one(load_f64(&m.fps_out))
+        });
+        family("gst_camera_dropped_frames_total", "counter", "Frames dropped, by reason.", &|m| {
+            vec![
+                (String::from(",reason=\"qos\""), load(&m.dropped_qos)),
+                (String::from(",reason=\"corrupt\""), load(&m.dropped_corrupt)),
+            ]
+        });
+        family("gst_camera_encoder_bitrate_bits_per_second", "gauge", "Target bitrate of the encoder.", &|m| {
+            match m.encoder_bitrate.load(Ordering::Relaxed) {
+                0 => vec![],
+                bitrate => one(bitrate as f64),
+            }
+        });
+        family("gst_camera_encode_queue_buffers", "gauge", "Buffers waiting in the encode queue.", &|m| {
+            one(load(&m.queue_buffers))
+        });
+        family("gst_camera_encode_queue_bytes", "gauge", "Bytes waiting in the encode queue.", &|m| {
+            one(load(&m.queue_bytes))
+        });
+        family("gst_camera_encode_queue_seconds", "gauge", "Duration of the data in the encode queue.", &|m| {
+            one(load(&m.queue_time) / 1e9)
+        });
+        family("gst_camera_encode_queue_fill_ratio", "gauge", "Fill level of the encode queue, 1 when full.", &|m| {
+            one(load_f64(&m.queue_fill))
+        });
+        family("gst_camera_segments_written_total", "counter", "Segments finished.", &|m| {
+            one(load(&m.segments))
+        });
+        family("gst_camera_bytes_written_total", "counter", "Bytes in finished segments.", &|m| {
+            one(load(&m.bytes))
+        });
+        family("gst_camera_restarts_total", "counter", "Pipeline restarts after failures.", &|m| {
+            one(load(&m.restarts))
+        });
+        family("gst_camera_errors_total", "counter", "Pipeline failures.", &|m| {
+            one(load(&m.errors))
+        });
+
+        out
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Serves the metrics of `recorders` on `GET /metrics`.
+pub fn handler(recorders: Vec<Arc<Metrics>>) -> Handler {
+    Arc::new(move |request: &Request| match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/metrics") => Response::new(
+            200,
+            "text/plain; version=0.0.4; charset=utf-8",
+            Metrics::render(&recorders),
+        ),
+        (_, "/metrics") => Response::text(405, "Method not allowed\n"),
+        _ => Response::not_found(),
+    })
+}
+
+/// Updates the rates and levels of a running pipeline every time it is
+/// sampled.
+pub(crate) struct MetricsSampler {
+    metrics: Arc<Metrics>,
+    pipeline: gst::Pipeline,
+    last: Instant,
+    last_in: u64,
+    last_out: u64,
+}
+
+impl MetricsSampler {
+    pub(crate) fn new(metrics: &Arc<Metrics>, pipeline: &gst::Pipeline) -> MetricsSampler {
+        MetricsSampler {
+            last: Instant::now(),
+            last_in: metrics.frames_in.load(Ordering::Relaxed),
+            last_out: metrics.frames_out.load(Ordering::Relaxed),
+            metrics: metrics.clone(),
+            pipeline: pipeline.clone(),
+        }
+    }
+
+    pub(crate) fn sample(&mut self) {
+        let metrics = &self.metrics;
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        let frames_in = metrics.frames_in.load(Ordering::Relaxed);
+        let frames_out = metrics.frames_out.load(Ordering::Relaxed);
+        if elapsed > 0.0 {
+            let fps_in = (frames_in - self.last_in) as f64 / elapsed;
+            let fps_out = (frames_out - self.last_out) as f64 / elapsed;
+            metrics.fps_in.store(fps_in.to_bits(), Ordering::Relaxed);
+            metrics.fps_out.store(fps_out.to_bits(), Ordering::Relaxed);
+        }
+        self.last = now;
+        self.last_in = frames_in;
+        self.last_out = frames_out;
+
+        let bitrate = self.pipeline.get_by_name("encoder")
+            .and_then(|encoder| encoder::current_bitrate(&encoder))
+            .map(|kbps| u64::from(kbps) * 1000)
+            .unwrap_or(0);
+        metrics.encoder_bitrate.store(bitrate, Ordering::Relaxed);
+
+        if let Some(queue) = self.pipeline.get_by_name("encode_queue") {
+            let levels = [
+                (&metrics.queue_buffers, "current-level-buffers", "max-size-buffers"),
+                (&metrics.queue_bytes, "current-level-bytes", "max-size-bytes"),
+                (&metrics.queue_time, "current-level-time", "max-size-time"),
+            ];
+            let mut fill = 0f64;
+            for (gauge, level, max) in levels.iter() {
+                let level = u64_property(&queue, level);
+                let max = u64_property(&queue, max);
+                gauge.store(level, Ordering::Relaxed);
+                if max > 0 {
+                    fill = fill.max(level as f64 / max as f64);
+                }
+            }
+            metrics.queue_fill.store(fill.to_bits(), Ordering::Relaxed);
+        }
+    }
+}
+
+fn u64_property(element: &gst::Element, name: &str) -> u64 {
+    element.get_property(name)
+        .ok()
+        .and_then(|value| value.get::<u32>().map(u64::from).or_else(|| value.get::<u64>()))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testutil::exchange;
+
+    fn dropped_qos(metrics: &Metrics) -> u64 {
+        metrics.dropped_qos.load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn counts_newly_dropped_frames_per_element() {
+        let metrics = Metrics::new("front");
+        metrics.qos("encoder", 5);
+        metrics.qos("encoder", 8);
+        metrics.qos("videoconvert", 2);
+        assert_eq!(dropped_qos(&metrics), 10);
+
+        // a total below the last one starts over
+        metrics.qos("encoder", 3);
+        assert_eq!(dropped_qos(&metrics), 13);
+
+        metrics.restarted();
+        metrics.qos("videoconvert", 4);
+        assert_eq!(dropped_qos(&metrics), 17);
+    }
+
+    #[test]
+    fn renders_every_camera() {
+        let front = Arc::new(Metrics::new("front \"door\""));
+        front.qos("encoder", 3);
+        front.corrupt_frame();
+        front.segment_written(1000);
+        front.segment_written(500);
+        let back = Arc::new(Metrics::new("back"));
+        back.encoder_bitrate.store(2_000_000, Ordering::Relaxed);
+        back.error();
+
+        let out = Metrics::render(&[front, back]);
+        let lines: Vec<&str> = out.lines().collect();
+        for expected in &[
+            "# HELP gst_camera_dropped_frames_total Frames dropped, by reason.",
+            "# TYPE gst_camera_dropped_frames_total counter",
+            r#"gst_camera_dropped_frames_total{camera="front \"door\"",reason="qos"} 3"#,
+            r#"gst_camera_dropped_frames_total{camera="front \"door\"",reason="corrupt"} 1"#,
+            r#"gst_camera_segments_written_total{camera="front \"door\""} 2"#,
+            r#"gst_camera_bytes_written_total{camera="front \"door\""} 1500"#,
+            r#"gst_camera_encoder_bitrate_bits_per_second{camera="back"} 2000000"#,
+            r#"gst_camera_errors_total{camera="back"} 1"#,
+            r#"gst_camera_fps_in{camera="back"} 0"#,
+        ] {
+            assert!(lines.contains(expected), "no {} in\n{}", expected, out);
+        }
+        // without an encoder there is no bitrate
+        assert!(!out.contains(r#"gst_camera_encoder_bitrate_bits_per_second{camera="front"#), "{}", out);
+    }
+
+    #[test]
+    fn serves_the_metrics() {
+        let handler = handler(vec![Arc::new(Metrics::new("front"))]);
+        let response = exchange(&handler, "GET /metrics HTTP/1.1\r\n\r\n");
+        assert!(
+            response.starts_with("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\n"),
+            "{}",
+            response,
+        );
+        assert!(response.contains("gst_camera_restarts_total{camera=\"front\"} 0\n"), "{}", response);
+        assert!(exchange(&handler, "POST /metrics HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 405 "));
+    }
+}
@@ -0,0 +1,308 @@
+use gstreamer as gst;
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::RecorderError;
+
+/// Pixel format of a camera mode.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PixelFormat {
+    Mjpeg,
+    /// Raw video, named like the `format` field of `video/x-raw`, e.g. YUY2.
+    Raw(String),
+}
+
+impl FromStr for PixelFormat {
+    type Err = RecorderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mjpeg" | "mjpg" | "jpeg" => Ok(PixelFormat::Mjpeg),
+            // V4L2 calls it YUYV, GStreamer YUY2
+            "yuyv" => Ok(PixelFormat::Raw(String::from("YUY2"))),
+            "" => Err(RecorderError::UnknownValue { kind: "pixel format", value: s.into() }),
+            _ => Ok(PixelFormat::Raw(s.to_uppercase())),
+        }
+    }
+}
+
+impl fmt::Display for PixelFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PixelFormat::Mjpeg => f.write_str("MJPEG"),
+            PixelFormat::Raw(format) => f.write_str(format),
+        }
+    }
+}
+
+/// A single format/resolution/framerate combination offered by a camera.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VideoMode {
+    pub format: PixelFormat,
+    pub width: i32,
+    pub height: i32,
+    pub framerate: gst::Fraction,
+}
+
+impl VideoMode {
+    /// Caps fixing the source to exactly this mode.
+    pub fn caps(&self) -> gst::Caps {
+        let builder = match &self.format {
+            PixelFormat::Mjpeg => gst::Caps::builder("image/jpeg"),
+            PixelFormat::Raw(format) => gst::Caps::builder("video/x-raw")
+                .field("format", &format.as_str()),
+        };
+        builder
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("framerate", &self.framerate)
+            .build()
+    }
+}
+
+impl fmt::Display for VideoMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {}x{} @ {} fps",
+            self.format, self.width, self.height, self.framerate
+        )
+    }
+}
+
+/// What the user asks of the camera mode. Unset fields don't constrain.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModeConstraints {
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub framerate: Option<i32>,
+    pub max_width: Option<i32>,
+    pub max_height: Option<i32>,
+    pub min_framerate: Option<i32>,
+    /// Only modes of this format are considered.
+    pub format: Option<PixelFormat>,
+    /// Wins over other formats at the same resolution.
+    pub prefer_format: Option<PixelFormat>,
+}
+
+impl ModeConstraints {
+    pub fn matches(&self, mode: &VideoMode) -> bool {
+        let exact = |wanted: Option<i32>, actual: i32| wanted.map(|w| w == actual).unwrap_or(true);
+        let max = |limit: Option<i32>, actual: i32| limit.map(|l| actual <= l).unwrap_or(true);
+
+        exact(self.width, mode.width)
+            && exact(self.height, mode.height)
+            && self.framerate
+                .map(|f| mode.framerate == gst::Fraction::new(f, 1))
+                .unwrap_or(true)
+            && max(self.max_width, mode.width)
+            && max(self.max_height, mode.height)
+            && self.min_framerate
+                .map(|f| mode.framerate >= gst::Fraction::new(f, 1))
+                .unwrap_or(true)
+            && self.format.as_ref().map(|f| *f == mode.format).unwrap_or(true)
+    }
+
+    /// Picks the best matching mode: highest resolution, then the preferred
+    /// format, then highest framerate.
+    pub fn select<'a>(&self, modes: &'a [VideoMode]) -> Option<&'a VideoMode> {
+        modes.iter()
+            .filter(|mode| self.matches(mode))
+            .max_by_key(|mode| {
+                let preferred = self.prefer_format.as_ref() == Some(&mode.format);
+                (i64::from(mode.width) * i64::from(mode.height), preferred, mode.framerate)
+            })
+    }
+}
+
+impl fmt::Display for ModeConstraints {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(format) = &self.format {
+            parts.push(format!("format {}", format));
+        }
+        if let Some(width) = self.width {
+            parts.push(format!("width {}", width));
+        }
+        if let Some(height) = self.height {
+            parts.push(format!("height {}", height));
+        }
+        if let Some(framerate) = self.framerate {
+            parts.push(format!("{} fps", framerate));
+        }
+        if let Some(max_width) = self.max_width {
+            parts.push(format!("max width {}", max_width));
+        }
+        if let Some(max_height) = self.max_height {
+            parts.push(format!("max height {}", max_height));
+        }
+        if let Some(min_framerate) = self.min_framerate {
+            parts.push(format!("at least {} fps", min_framerate));
+        }
+
+        if parts.is_empty() {
+            f.write_str("any mode")
+        } else {
+            f.write_str(&parts.join(", "))
+        }
+    }
+}
+
+/// Expands `caps` into the individual modes they describe.
+///
+/// Framerate lists become one mode per framerate, framerate ranges only
+/// contribute their maximum. Structures other than MJPEG and raw video, or
+/// with non-fixed sizes, are skipped.
+pub fn modes_from_caps(caps: &gst::CapsRef) -> Vec<VideoMode> {
+    let mut modes = Vec::new();
+
+    for s in caps.iter() {
+        let formats = match s.get_name() {
+            "image/jpeg" => vec![PixelFormat::Mjpeg],
+            "video/x-raw" => {
+                if let Some(format) = s.get::<String>("format") {
+                    vec![PixelFormat::Raw(format)]
+                } else if let Some(list) = s.get::<gst::List>("format") {
+                    list.as_slice()
+                        .iter()
+                        .filter_map(|v| v.get::<String>())
+                        .map(PixelFormat::Raw)
+                        .collect()
+                } else {
+                    continue;
+                }
+            }
+            _ => continue,
+        };
+
+        let (width, height) = match (s.get::<i32>("width"), s.get::<i32>("height")) {
+            (Some(width), Some(height)) => (width, height),
+            _ => continue,
+        };
+
+        let framerates = if let Some(framerate) = s.get::<gst::Fraction>("framerate") {
+            vec![framerate]
+        } else if let Some(list) = s.get::<gst::List>("framerate") {
+            list.as_slice()
+                .iter()
+                .filter_map(|v| v.get::<gst::Fraction>())
+                .collect()
+        } else if let Some(range) = s.get::<gst::FractionRange>("framerate") {
+            vec![range.max()]
+        } else {
+            continue;
+        };
+
+        for format in &formats {
+            for framerate in &framerates {
+                let mode = VideoMode {
+                    format: format.clone(),
+                    width,
+                    height,
+                    framerate: *framerate,
+                };
+                if !modes.contains(&mode) {
+                    modes.push(mode);
+                }
+            }
+        }
+    }
+
+    modes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fractions need GStreamer initialized.
+    fn mode(format: &str, width: i32, height: i32, framerate: i32) -> VideoMode {
+        gst::init().unwrap();
+        VideoMode {
+            format: format.parse().unwrap(),
+            width,
+            height,
+            framerate: gst::Fraction::new(framerate, 1),
+        }
+    }
+
+    fn modes() -> Vec<VideoMode> {
+        vec![
+            mode("yuyv", 640, 480, 30),
+            mode("yuyv", 1280, 720, 10),
+            mode("mjpeg", 1280, 720, 30),
+            mode("mjpeg", 1280, 720, 60),
+            mode("mjpeg", 1920, 1080, 30),
+            mode("yuyv", 1920, 1080, 5),
+        ]
+    }
+
+    fn select(constraints: ModeConstraints) -> Option<VideoMode> {
+        constraints.select(&modes()).cloned()
+    }
+
+    #[test]
+    fn parses_pixel_formats() {
+        assert_eq!("MJPG".parse::<PixelFormat>().unwrap(), PixelFormat::Mjpeg);
+        assert_eq!("yuyv".parse::<PixelFormat>().unwrap(), PixelFormat::Raw(String::from("YUY2")));
+        assert_eq!("nv12".parse::<PixelFormat>().unwrap(), PixelFormat::Raw(String::from("NV12")));
+        assert!("".parse::<PixelFormat>().is_err());
+    }
+
+    #[test]
+    fn selects_the_highest_resolution_then_framerate() {
+        assert_eq!(select(ModeConstraints::default()), Some(mode("mjpeg", 1920, 1080, 30)));
+        assert_eq!(
+            select(ModeConstraints { max_height: Some(720), ..ModeConstraints::default() }),
+            Some(mode("mjpeg", 1280, 720, 60)),
+        );
+    }
+
+    #[test]
+    fn prefers_the_format_at_the_same_resolution_only() {
+        let prefer_yuyv = ModeConstraints {
+            prefer_format: Some(PixelFormat::Raw(String::from("YUY2"))),
+            ..ModeConstraints::default()
+        };
+        assert_eq!(select(prefer_yuyv.clone()), Some(mode("yuyv", 1920, 1080, 5)));
+        assert_eq!(
+            select(ModeConstraints { width: Some(1280), ..prefer_yuyv }),
+            Some(mode("yuyv", 1280, 720, 10)),
+        );
+    }
+
+    #[test]
+    fn honours_exact_values_and_limits() {
+        assert_eq!(
+            select(ModeConstraints { width: Some(640), height: Some(480), ..ModeConstraints::default() }),
+            Some(mode("yuyv", 640, 480, 30)),
+        );
+        assert_eq!(
+            select(ModeConstraints { framerate: Some(60), ..ModeConstraints::default() }),
+            Some(mode("mjpeg", 1280, 720, 60)),
+        );
+        assert_eq!(
+            select(ModeConstraints {
+                format: Some(PixelFormat::Raw(String::from("YUY2"))),
+                min_framerate: Some(10),
+                ..ModeConstraints::default()
+            }),
+            Some(mode("yuyv", 1280, 720, 10)),
+        );
+        assert_eq!(
+            select(ModeConstraints { max_width: Some(1280), min_framerate: Some(30), ..ModeConstraints::default() }),
+            Some(mode("mjpeg", 1280, 720, 60)),
+        );
+    }
+
+    #[test]
+    fn selects_nothing_without_a_match() {
+        assert_eq!(select(ModeConstraints { width: Some(3840), ..ModeConstraints::default() }), None);
+        assert_eq!(
+            select(ModeConstraints { max_width: Some(320), ..ModeConstraints::default() }),
+            None,
+        );
+        assert_eq!(ModeConstraints::default().select(&[]), None);
+    }
+}
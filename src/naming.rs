@@ -0,0 +1,363 @@
+//! Segment file names built from a template instead of splitmuxsink's
+//! printf-style index.
+//!
+//! A location containing `{` is a template with these fields:
+//!
+//! - `{camera}`: the camera name
+//! - `{seq}` or `{seq:<width>}`: the segment sequence number, zero padded to
+//!   5 digits or `<width>`
+//! - `{time:<format>}`: the wall-clock time the segment was opened at, with
+//!   strftime-like `<format>`, e.g. `{time:%Y%m%d-%H%M%S}`. A `/` in
+//!   `<format>` starts a directory, e.g. `{time:%Y/%m/%d}`, so `%D`, `%x`
+//!   and `%c`, which may print slashes, are not allowed.
+//!
+//! `{{` and `}}` stand for literal braces. Directories in the expanded path
+//! are created as needed, and an existing file is never overwritten: a
+//! `-1`, `-2`, ... suffix is added before the extension instead.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::InvalidSetting;
+
+const DEFAULT_SEQ_WIDTH: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Field {
+    Text(String),
+    Camera,
+    Seq { width: usize },
+    Time { format: String },
+}
+
+/// A parsed segment location template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocationTemplate {
+    fields: Vec<Field>,
+}
+
+impl LocationTemplate {
+    /// Whether `location` is a template rather than a printf-style pattern.
+    pub fn is_template(location: &str) -> bool {
+        location.contains('{')
+    }
+
+    /// Parses `location`, `None` if it isn't a template.
+    pub fn from_location(location: &str) -> Result<Option<LocationTemplate>, InvalidSetting> {
+        if Self::is_template(location) {
+            Self::parse(location).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn parse(template: &str) -> Result<LocationTemplate, InvalidSetting> {
+        let invalid = |reason: String| InvalidSetting::new("output.location", reason);
+
+        let mut fields = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    let mut terminated = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            terminated = true;
+                            break;
+                        }
+                        name.push(c);
+                    }
+                    if !terminated {
+                        return Err(invalid(String::from("unterminated {, use {{ for a literal brace")));
+                    }
+                    let mut parts = name.splitn(2, ':');
+                    let field = match (parts.next().unwrap_or(""), parts.next()) {
+                        ("camera", None) => Field::Camera,
+                        ("seq", None) => Field::Seq { width: DEFAULT_SEQ_WIDTH },
+                        ("seq", Some(width)) => Field::Seq {
+                            width: width.parse()
+                                .map_err(|_| invalid(format!("invalid sequence width in {{{}}}", name)))?,
+                        },
+                        ("time", Some(format)) if !format.is_empty() => {
+                            if let Some(conversion) = slash_conversion(format) {
+                                return Err(invalid(format!(
+                                    "%{} in {{{}}} may print a /, spell the date out like %m/%d/%y",
+                                    conversion, name,
+                                )));
+                            }
+                            Field::Time { format: format.into() }
+                        }
+                        _ => return Err(invalid(format!("unknown field {{{}}}", name))),
+                    };
+                    if !text.is_empty() {
+                        fields.push(Field::Text(text.split_off(0)));
+                    }
+                    fields.push(field);
+                }
+                '}' => return Err(invalid(String::from("unmatched }, use }} for a literal brace"))),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            fields.push(Field::Text(text));
+        }
+
+        Ok(LocationTemplate { fields })
+    }
+
+    /// Expands the template for segment `seq` opened now.
+    pub fn format(&self, camera: &str, seq: u32) -> String {
+        let mut now = None;
+
+        self.fields
+            .iter()
+            .map(|field| match field {
+                Field::Text(text) => text.clone(),
+                Field::Camera => camera.to_string(),
+                Field::Seq { width } => format!("{:0width$}", seq, width = width),
+                Field::Time { format } => now.get_or_insert_with(glib::DateTime::new_now_local)
+                    .format(format)
+                    .map(|s| s.to_string())
+                    .unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    /// Wildcard pattern matching every segment of `camera`, `*` standing
+    /// for sequence numbers, times and collision suffixes. The directories
+    /// a time format starts are kept, so the pattern matches per path
+    /// component.
+    pub fn glob(&self, camera: &str) -> String {
+        let mut glob = String::new();
+        for field in &self.fields {
+            match field {
+                Field::Text(text) => glob.push_str(text),
+                Field::Camera => glob.push_str(camera),
+                Field::Seq { .. } => push_wildcard(&mut glob),
+                Field::Time { format } => {
+                    for _ in format.matches('/') {
+                        push_wildcard(&mut glob);
+                        glob.push('/');
+                    }
+                    push_wildcard(&mut glob);
+                }
+            }
+        }
+        glob
+    }
+
+    /// Expands the template and returns a path that doesn't exist yet, with
+    /// its parent directory created.
+    pub fn next_location(&self, camera: &str, seq: u32) -> io::Result<PathBuf> {
+        let path = PathBuf::from(self.format(camera, seq));
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        unique_path(&path)
+    }
+}
+
+fn push_wildcard(glob: &mut String) {
+    if !glob.ends_with('*') {
+        glob.push('*');
+    }
+}
+
+/// The first conversion in the time `format` whose output may contain a
+/// `/`, which would add directories the glob doesn't know about.
+fn slash_conversion(format: &str) -> Option<char> {
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            continue;
+        }
+        if let Some(conversion @ ('D' | 'x' | 'c')) = chars.next() {
+            return Some(conversion);
+        }
+    }
+    None
+}
+
+/// Wildcard pattern matching every segment written to `location`, be it a
+/// template or a printf-style pattern like `video%05d.mp4`.
+pub fn segment_glob(location: &str, camera: &str) -> Result<String, InvalidSetting> {
+    if let Some(template) = LocationTemplate::from_location(location)? {
+        return Ok(template.glob(camera));
+    }
+
+    let mut glob = String::new();
+    let mut chars = location.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            glob.push(c);
+            continue;
+        }
+        if chars.peek() == Some(&'%') {
+            chars.next();
+            glob.push('%');
+            continue;
+        }
+        // %d, %05d and the like
+        while chars.peek().map(|c| c.is_ascii_digit()).unwrap_or(false) {
+            chars.next();
+        }
+        chars.next();
+        glob.push('*');
+    }
+    Ok(glob)
+}
+
+/// Matches a single path component against `pattern`, where `*` matches
+/// any run of characters.
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    match pattern.find('*') {
+        None => pattern == name,
+        Some(star) => {
+            let (prefix, rest) = (&pattern[..star], &pattern[star + 1..]);
+            if !name.starts_with(prefix) {
+                return false;
+            }
+            let name = &name[prefix.len()..];
+            (0..=name.len())
+                .filter(|&i| name.is_char_boundary(i))
+                .any(|i| wildcard_match(rest, &name[i..]))
+        }
+    }
+}
+
+/// `path`, or `path` with the first free `-<n>` suffix before its extension.
+fn unique_path(path: &Path) -> io::Result<PathBuf> {
+    if !path.exists() {
+        return Ok(path.to_path_buf());
+    }
+
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let extension = path.extension().map(|e| e.to_string_lossy().into_owned());
+    (1..=u32::MAX)
+        .map(|n| {
+            let file_name = match &extension {
+                Some(extension) => format!("{}-{}.{}", stem, n, extension),
+                None => format!("{}-{}", stem, n),
+            };
+            path.with_file_name(file_name)
+        })
+        .find(|candidate| !candidate.exists())
+        .ok_or_else(|| io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("no free suffix left for {}", path.display()),
+        ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testutil::temp_dir;
+
+    fn parse_error(template: &str) -> String {
+        LocationTemplate::parse(template).unwrap_err().reason
+    }
+
+    #[test]
+    fn parses_fields_and_text() {
+        let template = LocationTemplate::parse("{camera}/{{x}}-{seq}-{seq:3}-{time:%H%M}.mkv").unwrap();
+        assert_eq!(template.fields, vec![
+            Field::Camera,
+            Field::Text(String::from("/{x}-")),
+            Field::Seq { width: 5 },
+            Field::Text(String::from("-")),
+            Field::Seq { width: 3 },
+            Field::Text(String::from("-")),
+            Field::Time { format: String::from("%H%M") },
+            Field::Text(String::from(".mkv")),
+        ]);
+    }
+
+    #[test]
+    fn rejects_malformed_templates() {
+        assert_eq!(parse_error("{camera"), "unterminated {, use {{ for a literal brace");
+        assert_eq!(parse_error("video-{seq.mkv"), "unterminated {, use {{ for a literal brace");
+        assert_eq!(parse_error("video}.mkv"), "unmatched }, use }} for a literal brace");
+        assert_eq!(parse_error("{index}.mkv"), "unknown field {index}");
+        assert_eq!(parse_error("{time:}.mkv"), "unknown field {time:}");
+        assert_eq!(parse_error("{seq:x}.mkv"), "invalid sequence width in {seq:x}");
+        assert_eq!(
+            parse_error("{time:%Y-%D}.mkv"),
+            "%D in {time:%Y-%D} may print a /, spell the date out like %m/%d/%y",
+        );
+        assert!(LocationTemplate::parse("{time:100%%D}.mkv").is_ok());
+    }
+
+    #[test]
+    fn only_braces_make_a_template() {
+        assert_eq!(LocationTemplate::from_location("video%05d.mp4").unwrap(), None);
+        assert!(LocationTemplate::from_location("{seq}.mp4").unwrap().is_some());
+    }
+
+    #[test]
+    fn formats_camera_and_sequence() {
+        let template = LocationTemplate::parse("{camera}/{seq}-{seq:3}-{{}}.mkv").unwrap();
+        assert_eq!(template.format("front", 7), "front/00007-007-{}.mkv");
+    }
+
+    #[test]
+    fn globs_variable_fields() {
+        let template = LocationTemplate::parse("{camera}/{time:%Y%m%d}{seq}-{seq}.mkv").unwrap();
+        assert_eq!(template.glob("front"), "front/*-*.mkv");
+        assert_eq!(segment_glob("/rec/{camera}-{seq}.mkv", "back").unwrap(), "/rec/back-*.mkv");
+        assert_eq!(
+            segment_glob("/rec/{time:%Y/%m/%d}/{camera}-{seq}.mkv", "back").unwrap(),
+            "/rec/*/*/*/back-*.mkv",
+        );
+        assert_eq!(segment_glob("/rec/{time:%Y/day-%d-}{seq}.mkv", "back").unwrap(), "/rec/*/*.mkv");
+    }
+
+    #[test]
+    fn globs_printf_patterns() {
+        assert_eq!(segment_glob("video%05d.mp4", "front").unwrap(), "video*.mp4");
+        assert_eq!(segment_glob("video%d-100%%.mp4", "front").unwrap(), "video*-100%.mp4");
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(wildcard_match("video*.mp4", "video00001.mp4"));
+        assert!(wildcard_match("video*.mp4", "video.mp4"));
+        assert!(wildcard_match("*-*.mkv", "20240101-00001-1.mkv"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("exact.mkv", "exact.mkv"));
+        assert!(!wildcard_match("video*.mp4", "video00001.mkv"));
+        assert!(!wildcard_match("*-*.mkv", "00001.mkv"));
+        assert!(!wildcard_match("exact.mkv", "exact.mkv.tmp"));
+    }
+
+    #[test]
+    fn never_overwrites_segments() {
+        let dir = temp_dir("naming");
+        let template = LocationTemplate::parse(&format!("{}/{{camera}}/{{seq}}.mkv", dir.display())).unwrap();
+
+        let first = template.next_location("front", 1).unwrap();
+        assert_eq!(first, dir.join("front/00001.mkv"));
+        assert!(dir.join("front").is_dir());
+
+        fs::write(&first, b"").unwrap();
+        let second = template.next_location("front", 1).unwrap();
+        assert_eq!(second, dir.join("front/00001-1.mkv"));
+
+        fs::write(&second, b"").unwrap();
+        assert_eq!(template.next_location("front", 1).unwrap(), dir.join("front/00001-2.mkv"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
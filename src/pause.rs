@@ -0,0 +1,206 @@
+//! Pausing recording in front of the encoder while the source keeps
+//! running, so resuming doesn't wait for the camera to start again.
+//!
+//! Pausing closes `record_valve` and sends EOS past it, which finalizes
+//! the current segment. Resuming flushes the EOS out of the valve and
+//! resets everything downstream of it: the encoder starts over on a
+//! keyframe and splitmuxsink opens a new segment with the first frame let
+//! through.
+
+use gstreamer as gst;
+use gst::prelude::*;
+
+use crate::error::RecorderError;
+
+/// Why recording is paused, frames are recorded while neither holds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct PauseReasons {
+    /// Through [`crate::CameraRecorder::pause`].
+    pub requested: bool,
+    /// By the storage policy, see [`crate::storage::LowSpaceAction::Pause`].
+    pub low_space: bool,
+}
+
+impl PauseReasons {
+    pub fn is_paused(self) -> bool {
+        self.requested || self.low_space
+    }
+
+    /// Applies `update`, returns whether recording has to be paused
+    /// (`Some(true)`) or resumed (`Some(false)`) for it.
+    pub fn update<F: FnOnce(&mut PauseReasons)>(&mut self, update: F) -> Option<bool> {
+        let was_paused = self.is_paused();
+        update(self);
+        Some(self.is_paused()).filter(|&paused| paused != was_paused)
+    }
+}
+
+/// Drops frames at the valve and finalizes the segment being written.
+pub(crate) fn pause(pipeline: &gst::Pipeline) -> Result<(), RecorderError> {
+    let valve = valve(pipeline)?;
+    valve.set_property("drop", &true)?;
+
+    // once no frame is on its way through, nothing else gets past the valve
+    let pad = src_pad(&valve)?;
+    pad.add_probe(gst::PadProbeType::IDLE, |pad, _| {
+        pad.push_event(gst::Event::new_eos().build());
+        gst::PadProbeReturn::Remove
+    });
+
+    Ok(())
+}
+
+/// Lets frames through the valve again into a new segment with index
+/// `next_index`.
+pub(crate) fn resume(pipeline: &gst::Pipeline, next_index: u32) -> Result<(), RecorderError> {
+    let valve = valve(pipeline)?;
+    let pad = src_pad(&valve)?;
+    let peer = pad.get_peer()
+        .ok_or_else(|| RecorderError::pipeline("record_valve is not linked"))?;
+
+    // back from EOS, and the encoder from the start
+    let downstream = downstream_of(&valve);
+    for element in &downstream {
+        element.set_state(gst::State::Null)?;
+    }
+    if let Some(splitmuxsink) = pipeline.get_by_name("splitmuxsink") {
+        splitmuxsink.set_property("start-index", &(next_index as i32).to_value())?;
+    }
+
+    // the valve would answer every frame with EOS and send the EOS again,
+    // flushing clears it along with the segment
+    pad.push_event(gst::Event::new_flush_start().build());
+    pad.push_event(gst::Event::new_flush_stop(false).build());
+    pad.unlink(&peer)?;
+    let sink_pad = valve.get_static_pad("sink")
+        .ok_or_else(|| RecorderError::pipeline("record_valve has no sink pad"))?;
+    let mut stored = Ok(());
+    sink_pad.sticky_events_foreach(|event| {
+        if let Err(err) = pad.store_sticky_event(&event) {
+            stored = Err(RecorderError::pipeline(format!("cannot restore {:?}: {:?}", event.get_type(), err)));
+        }
+        Ok(Some(event))
+    });
+    stored?;
+
+    // relinking has the valve send the stream's caps and segment again,
+    // resetting cleared them
+    pad.link(&peer)?;
+    for element in downstream.iter().rev() {
+        element.sync_state_with_parent()?;
+    }
+
+    valve.set_property("drop", &false)?;
+
+    Ok(())
+}
+
+fn valve(pipeline: &gst::Pipeline) -> Result<gst::Element, RecorderError> {
+    pipeline.get_by_name("record_valve").ok_or(RecorderError::MissingElement("valve"))
+}
+
+fn src_pad(element: &gst::Element) -> Result<gst::Pad, RecorderError> {
+    element.get_static_pad("src")
+        .ok_or_else(|| RecorderError::pipeline(format!("{} has no src pad", element.get_name())))
+}
+
+/// Every element after `element` up to the sink, in order.
+fn downstream_of(element: &gst::Element) -> Vec<gst::Element> {
+    let mut elements = Vec::new();
+    let mut current = element.clone();
+    while let Some(next) = current.get_static_pad("src")
+        .and_then(|pad| pad.get_peer())
+        .and_then(|peer| peer.get_parent_element())
+    {
+        elements.push(next.clone());
+        current = next;
+    }
+    elements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::time::{Duration, Instant};
+
+    use crate::testutil::temp_dir;
+
+    #[test]
+    fn pauses_and_resumes_on_the_first_and_last_reason() {
+        let mut reasons = PauseReasons::default();
+
+        assert_eq!(reasons.update(|r| r.requested = true), Some(true));
+        assert_eq!(reasons.update(|r| r.low_space = true), None);
+        assert_eq!(reasons.update(|r| r.requested = false), None);
+        assert_eq!(reasons.update(|r| r.low_space = false), Some(false));
+        assert_eq!(reasons.update(|r| r.low_space = false), None);
+    }
+
+    #[test]
+    fn resumes_once_space_is_recovered() {
+        let mut reasons = PauseReasons::default();
+
+        assert_eq!(reasons.update(|r| r.low_space = true), Some(true));
+        assert!(reasons.is_paused());
+        assert_eq!(reasons.update(|r| r.low_space = false), Some(false));
+        assert!(!reasons.is_paused());
+    }
+
+    /// Runs `pipeline` for up to `duration`, failing on errors, returns
+    /// whether it posted EOS.
+    fn run_for(pipeline: &gst::Pipeline, duration: Duration) -> bool {
+        let bus = pipeline.get_bus().unwrap();
+        let deadline = Instant::now() + duration;
+        while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+            let msg = match bus.timed_pop(gst::ClockTime::from_mseconds(left.as_millis() as u64)) {
+                Some(msg) => msg,
+                None => break,
+            };
+            match msg.view() {
+                gst::MessageView::Eos(..) => return true,
+                gst::MessageView::Error(err) => panic!("{}", err.get_error()),
+                _ => (),
+            }
+        }
+        false
+    }
+
+    /// Needs GStreamer with the base and good plugins.
+    #[test]
+    fn resuming_writes_a_new_segment() {
+        gst::init().unwrap();
+        let dir = temp_dir("pause");
+
+        let pipeline = gst::Pipeline::new(None);
+        let source = gst::ElementFactory::make("videotestsrc", None).unwrap();
+        source.set_property("is-live", &true).unwrap();
+        let valve = gst::ElementFactory::make("valve", "record_valve").unwrap();
+        let queue = gst::ElementFactory::make("queue", None).unwrap();
+        let encoder = gst::ElementFactory::make("jpegenc", None).unwrap();
+        let splitmuxsink = gst::ElementFactory::make("splitmuxsink", "splitmuxsink").unwrap();
+        splitmuxsink.set_property("location", &dir.join("segment%05d.mp4").to_string_lossy().to_string()).unwrap();
+        let elements = [&source, &valve, &queue, &encoder, &splitmuxsink];
+        pipeline.add_many(&elements).unwrap();
+        gst::Element::link_many(&elements).unwrap();
+
+        pipeline.set_state(gst::State::Playing).unwrap();
+        assert!(!run_for(&pipeline, Duration::from_millis(500)));
+
+        pause(&pipeline).unwrap();
+        assert!(run_for(&pipeline, Duration::from_secs(5)), "pausing finalizes the segment");
+
+        resume(&pipeline, 1).unwrap();
+        assert!(!run_for(&pipeline, Duration::from_millis(500)));
+        pipeline.send_event(gst::Event::new_eos().build());
+        assert!(run_for(&pipeline, Duration::from_secs(5)));
+        pipeline.set_state(gst::State::Null).unwrap();
+
+        for segment in &["segment00000.mp4", "segment00001.mp4"] {
+            let size = fs::metadata(dir.join(segment)).map(|m| m.len()).unwrap_or(0);
+            assert!(size > 0, "{} is empty", segment);
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
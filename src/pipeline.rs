@@ -0,0 +1,182 @@
+use gstreamer as gst;
+use gst::prelude::*;
+
+use crate::config::RecorderConfig;
+use crate::error::{ErrorMessage, RecorderError};
+use crate::modes::PixelFormat;
+use crate::naming::LocationTemplate;
+use crate::slate::SlateConfig;
+
+const RECORD_VALVE: &str = "valve";
+const ENCODE_QUEUE: &str = "queue";
+const SINK: &str = "splitmuxsink";
+
+pub fn make_element<'a, P: Into<Option<&'a str>>>(
+    factory_name: &'static str,
+    element_name: P,
+) -> Result<gst::Element, RecorderError> {
+    match gst::ElementFactory::make(factory_name, element_name.into()) {
+        Some(elem) => Ok(elem),
+        None => Err(RecorderError::MissingElement(factory_name)),
+    }
+}
+
+/// Turns frames of `format` into raw video the encoders accept: MJPEG is
+/// decoded, raw formats like YUY2 only need converting.
+///
+/// Corrupt MJPEG frames are dropped with a warning rather than failing,
+/// see [`crate::corruption`].
+pub fn make_decoder(format: &PixelFormat) -> Result<gst::Element, RecorderError> {
+    let factory = decoder_factory(format);
+    let decoder = make_element(factory, factory)?;
+    if decoder.find_property("max-errors").is_some() {
+        decoder.set_property("max-errors", &-1i32)?;
+    }
+    Ok(decoder)
+}
+
+/// Element [`make_decoder`] makes for `format`.
+pub(crate) fn decoder_factory(format: &PixelFormat) -> &'static str {
+    match format {
+        PixelFormat::Mjpeg => "jpegdec",
+        PixelFormat::Raw(_) => "videoconvert",
+    }
+}
+
+/// Factories of every element [`build`] makes for `config`, for checking
+/// they are installed before recording.
+///
+/// Each entry lists alternatives of which one is enough, like the
+/// encoders tried in turn for a kind. Without the camera `format` known,
+/// the decoders for both MJPEG and raw modes are listed.
+pub(crate) fn factories(config: &RecorderConfig, format: Option<&PixelFormat>) -> Vec<Vec<&'static str>> {
+    let mut factories = config.source.factories(config.passthrough, format);
+    if config.slate.enabled {
+        factories.extend(SlateConfig::factories().iter().map(|factory| vec![*factory]));
+    }
+    factories.push(vec![RECORD_VALVE]);
+    factories.push(vec![ENCODE_QUEUE]);
+    if config.passthrough {
+        factories.extend(config.stream_format().parser().map(|parser| vec![parser]));
+    } else {
+        factories.extend(config.encoder.factories());
+    }
+    factories.push(vec![SINK]);
+    factories.push(vec![config.container().muxer_name()]);
+    factories
+}
+
+/// Builds the camera recording pipeline described by `config`, its first
+/// segment having index `start_index`.
+pub fn build(config: &RecorderConfig, start_index: u32) -> Result<gst::Pipeline, RecorderError> {
+    // create pipeline
+    let pipeline = gst::Pipeline::new("camera-recorder");
+
+    // region create elements
+    // video source, producing raw video or the camera's MJPEG
+    let source = if config.passthrough {
+        config.source.build_passthrough(&config.mode)?
+    } else {
+        config.source.build(&config.mode)?
+    };
+
+    // switching to a slate while the source sends nothing
+    let source = if config.slate.enabled {
+        config.slate.build_fallback(source)?
+    } else {
+        source
+    };
+    let source = source.upcast::<gst::Element>();
+
+    // valve, dropping frames while recording is paused
+    let record_valve = make_element(RECORD_VALVE, "record_valve")?;
+
+    // encode queue
+    let encode_queue = make_element(ENCODE_QUEUE, "encode_queue")?;
+
+    // encoder, caps filter and parser, only a parser for passthrough
+    let encoder = if config.passthrough {
+        let mut parser = Vec::new();
+        if let Some(factory) = config.stream_format().parser() {
+            parser.push(make_element(factory, "parser")?);
+        }
+        parser
+    } else {
+        config.encoder.build()?
+    };
+
+    // sink
+    let splitmuxsink = make_element(SINK, "splitmuxsink")?;
+    match LocationTemplate::from_location(&config.location)? {
+        Some(template) => {
+            // name every segment as it gets opened
+            let camera = config.name.clone();
+            splitmuxsink.connect("format-location-full", false, move |args| {
+                let fragment_id = args[1].get::<u32>().unwrap_or(0);
+                match template.next_location(&camera, fragment_id) {
+                    Ok(location) => Some(location.to_string_lossy().to_value()),
+                    Err(err) => {
+                        if let Some(splitmuxsink) = args[0].get::<gst::Element>() {
+                            gst::gst_element_error!(splitmuxsink, gst::ResourceError::OpenWrite, (&err.to_string()));
+                        }
+                        None
+                    }
+                }
+            })?;
+        }
+        None => splitmuxsink.set_property("location", &config.location)?,
+    }
+    // aligned splits are triggered by the recorder instead
+    let max_size_time = if config.align_splits { 0 } else { config.max_size_time.nseconds().unwrap_or(0) };
+    splitmuxsink.set_property("max-size-time", &max_size_time.to_value())?;
+    splitmuxsink.set_property("send-keyframe-requests", &true.to_value())?;
+    splitmuxsink.set_property("start-index", &(start_index as i32).to_value())?;
+    let muxer = config.muxer.build(&config.location, config.stream_format())?;
+    splitmuxsink.set_property("muxer", &muxer)?;
+    // endregion
+
+    // region set up the pipeline
+    // add elements
+    let mut elements = vec![&source, &record_valve, &encode_queue];
+    elements.extend(encoder.iter());
+    elements.push(&splitmuxsink);
+    pipeline.add_many(&elements)?;
+
+    // link elements
+    gst::Element::link_many(&elements)?;
+    // endregion
+
+    Ok(pipeline)
+}
+
+/// Plays `pipeline` until EOS and sets it back to NULL.
+///
+/// Meant for the short-lived helper pipelines (snapshot, verify, export),
+/// the recorder itself runs on a main loop.
+pub fn run_to_eos(pipeline: &gst::Pipeline) -> Result<(), RecorderError> {
+    let bus = pipeline.get_bus().ok_or(RecorderError::Watch)?;
+
+    pipeline.set_state(gst::State::Playing)?;
+
+    let mut result = Ok(());
+    for msg in bus.iter_timed(gst::CLOCK_TIME_NONE) {
+        use gst::MessageView;
+
+        match msg.view() {
+            MessageView::Eos(..) => break,
+            MessageView::Error(err) => {
+                result = Err(RecorderError::from(ErrorMessage::new(
+                    msg.get_src(),
+                    err.get_error(),
+                    err.get_debug().map(|d| d.to_string()),
+                )));
+                break;
+            }
+            _ => (),
+        }
+    }
+
+    pipeline.set_state(gst::State::Null)?;
+
+    result
+}
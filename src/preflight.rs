@@ -0,0 +1,168 @@
+//! Checks ahead of recording that every element the configured pipeline
+//! needs is installed and that the camera can be opened and delivers a
+//! matching mode.
+
+use gstreamer as gst;
+
+use std::fmt;
+use std::fs::OpenOptions;
+
+use crate::config::RecorderConfig;
+use crate::devices;
+use crate::error::RecorderError;
+use crate::modes::{ModeConstraints, PixelFormat, VideoMode};
+use crate::pipeline;
+
+/// GStreamer module shipping a plugin, as packaged by most distributions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Package {
+    Core,
+    Base,
+    Good,
+    Bad,
+    Ugly,
+    Libav,
+    Rs,
+}
+
+impl fmt::Display for Package {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Package::Core => "gstreamer",
+            Package::Base => "gst-plugins-base",
+            Package::Good => "gst-plugins-good",
+            Package::Bad => "gst-plugins-bad",
+            Package::Ugly => "gst-plugins-ugly",
+            Package::Libav => "gst-libav",
+            Package::Rs => "gst-plugins-rs",
+        })
+    }
+}
+
+/// Plugin and package providing every element the recorder may use.
+const PLUGINS: &[(&str, &str, Package)] = &[
+    ("capsfilter", "coreelements", Package::Core),
+    ("fakesink", "coreelements", Package::Core),
+    ("filesink", "coreelements", Package::Core),
+    ("input-selector", "coreelements", Package::Core),
+    ("queue", "coreelements", Package::Core),
+    ("valve", "coreelements", Package::Core),
+    ("clockoverlay", "pango", Package::Base),
+    ("textoverlay", "pango", Package::Base),
+    ("uridecodebin", "playback", Package::Base),
+    ("videoconvert", "videoconvert", Package::Base),
+    ("videotestsrc", "videotestsrc", Package::Base),
+    ("avimux", "avi", Package::Good),
+    ("jpegdec", "jpeg", Package::Good),
+    ("jpegenc", "jpeg", Package::Good),
+    ("matroskamux", "matroska", Package::Good),
+    ("mp4mux", "isomp4", Package::Good),
+    ("splitmuxsink", "multifile", Package::Good),
+    ("v4l2src", "video4linux2", Package::Good),
+    ("vp8enc", "vpx", Package::Good),
+    ("vp9enc", "vpx", Package::Good),
+    ("av1enc", "aom", Package::Bad),
+    ("av1parse", "videoparsersbad", Package::Bad),
+    ("h264parse", "videoparsersbad", Package::Bad),
+    ("h265parse", "videoparsersbad", Package::Bad),
+    ("jpegparse", "jpegformat", Package::Bad),
+    ("mpegtsmux", "mpegtsmux", Package::Bad),
+    ("x265enc", "x265", Package::Bad),
+    ("x264enc", "x264", Package::Ugly),
+    ("rav1enc", "rav1e", Package::Rs),
+];
+
+/// Plugin name and package of `element`, if it is one the recorder uses.
+pub fn plugin_of(element: &str) -> Option<(&'static str, Package)> {
+    PLUGINS.iter()
+        .find(|(name, _, _)| *name == element)
+        .map(|(_, plugin, package)| (*plugin, *package))
+}
+
+/// A needed element no installed plugin provides, any of `elements` would
+/// do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingElement {
+    pub elements: Vec<&'static str>,
+}
+
+impl fmt::Display for MissingElement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, element) in self.elements.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" or ")?;
+            }
+            match plugin_of(element) {
+                Some((plugin, package)) => write!(f, "{} (plugin {} from {})", element, plugin, package)?,
+                None => f.write_str(element)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of [`check`].
+#[derive(Debug)]
+pub struct Report {
+    pub missing: Vec<MissingElement>,
+    /// Mode the camera would record in, or why it can't, `None` for
+    /// sources other than cameras.
+    pub device: Option<Result<VideoMode, RecorderError>>,
+}
+
+impl Report {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.device.as_ref().map(Result::is_ok).unwrap_or(true)
+    }
+}
+
+/// Checks everything `config` needs that validating it can't.
+pub fn check(config: &RecorderConfig) -> Result<Report, RecorderError> {
+    gst::init()?;
+
+    let device = config.source.device().map(|device| check_device(device, config));
+    let format = match &device {
+        Some(Ok(mode)) => Some(mode.format.clone()),
+        _ => config.mode.format.clone(),
+    };
+
+    let missing = required_elements(config, format.as_ref())
+        .into_iter()
+        .filter(|elements| !elements.iter().any(|element| gst::ElementFactory::find(element).is_some()))
+        .map(|elements| MissingElement { elements })
+        .collect();
+
+    Ok(Report { missing, device })
+}
+
+/// Opens `device` and selects the mode it would record in.
+fn check_device(device: &str, config: &RecorderConfig) -> Result<VideoMode, RecorderError> {
+    let path = devices::resolve(device)?;
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .map_err(|e| RecorderError::DeviceInaccessible { device: path.clone(), reason: e.to_string() })?;
+
+    let constraints = if config.passthrough {
+        ModeConstraints {
+            format: Some(PixelFormat::Mjpeg),
+            ..config.mode.clone()
+        }
+    } else {
+        config.mode.clone()
+    };
+    devices::select_mode(&path, &constraints)
+}
+
+/// Every element [`crate::pipeline::build`] makes for `config`, as lists
+/// of alternatives, each listed once.
+fn required_elements(config: &RecorderConfig, format: Option<&PixelFormat>) -> Vec<Vec<&'static str>> {
+    let mut required: Vec<Vec<&'static str>> = Vec::new();
+    for elements in pipeline::factories(config, format) {
+        if !required.contains(&elements) {
+            required.push(elements);
+        }
+    }
+    required
+}
@@ -0,0 +1,1044 @@
+use gstreamer as gst;
+use gst::prelude::*;
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, trace, warn};
+
+use crate::config::RecorderConfig;
+use crate::corruption::CorruptionMonitor;
+use crate::devices;
+use crate::encoder;
+use crate::error::{ErrorMessage, InvalidSetting, RecorderError};
+use crate::metrics::{Metrics, MetricsSampler};
+use crate::pause::{self, PauseReasons};
+use crate::pipeline;
+use crate::retention;
+use crate::slate::SlateSwitch;
+use crate::snapshot;
+use crate::split::{self, SplitClock};
+use crate::storage::{self, LowSpaceAction};
+use crate::supervisor::Supervisor;
+use crate::watchdog::Watchdog;
+
+/// Something that happened inside a running recorder.
+#[derive(Debug, Clone)]
+pub enum RecorderEvent {
+    /// The pipeline switched to PLAYING.
+    Started,
+    StateChanged {
+        src: Option<String>,
+        old: gst::State,
+        current: gst::State,
+        pending: gst::State,
+    },
+    Warning(ErrorMessage),
+    /// splitmuxsink finished writing a segment.
+    SegmentClosed { location: String },
+    /// A segment was deleted by the retention policy.
+    SegmentDeleted(PathBuf),
+    /// Old segments couldn't be listed or deleted.
+    RetentionFailed(String),
+    /// Free space dropped below a threshold and `action` was taken.
+    LowSpace { free: u64, action: LowSpaceAction },
+    /// Free space went back above the threshold of `action`, which has
+    /// been undone.
+    SpaceRecovered { free: u64, action: LowSpaceAction },
+    /// The output filesystem is full, or about to be, and the storage
+    /// policy has nothing left to free space with.
+    DiskFull { free: u64 },
+    /// Free space couldn't be checked or the storage policy applied.
+    StorageFailed(String),
+    /// The pipeline couldn't be built or started.
+    PipelineFailed(String),
+    /// The pipeline failed and gets rebuilt after `delay`.
+    Restarting { attempt: u32, delay: Duration },
+    /// Too many restarts within the restart window, the recorder stops.
+    RestartLimitReached,
+    /// The camera is gone, the recorder waits for it to come back.
+    DeviceOffline { device: String },
+    /// The camera is back, recording resumes.
+    DeviceOnline { device: String },
+    /// The source stopped sending, the slate is recorded instead.
+    SignalLost,
+    /// The source sends again and is recorded instead of the slate.
+    SignalRestored,
+    /// The source has sent nothing for `since` while the pipeline kept
+    /// playing.
+    FrameStall { since: Duration },
+    /// The source sends again after a stall.
+    FramesResumed,
+    /// Recording was paused through [`CameraRecorder::pause`], the segment
+    /// being written gets finalized.
+    Paused,
+    /// Recording resumed into a new segment.
+    Resumed,
+    /// `dropped` corrupt MJPEG frames were dropped since the last report.
+    CorruptFrames { dropped: u64 },
+    /// `corrupt` of the `frames` received within the corruption window
+    /// were corrupt, more than the policy allows. The pipeline has been
+    /// shut down.
+    TooManyCorruptFrames { corrupt: u64, frames: u64 },
+    /// The pipeline posted an error and has been shut down.
+    Error(ErrorMessage),
+    Eos,
+    /// EOS didn't make it through the pipeline within the shutdown timeout,
+    /// the last segment may not be finalized.
+    ShutdownTimedOut,
+    /// The main loop has exited and the pipeline is back to NULL.
+    Stopped,
+}
+
+/// A recorder's state at one point in time, see [`CameraRecorder::status`].
+#[derive(Debug, Clone)]
+pub struct RecorderStatus {
+    pub running: bool,
+    /// Frames are dropped instead of recorded, see [`CameraRecorder::pause`].
+    pub paused: bool,
+    /// State of the pipeline, none while restarting.
+    pub state: Option<gst::State>,
+    /// Index of the next segment.
+    pub next_index: u32,
+    /// Encoder bitrate in kbit/s, none for passthrough recording.
+    pub bitrate: Option<u32>,
+}
+
+/// How long [`CameraRecorder::snapshot`] waits for a frame.
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Default)]
+struct Subscribers(Arc<Mutex<Vec<mpsc::Sender<RecorderEvent>>>>);
+
+impl Subscribers {
+    fn emit(&self, event: RecorderEvent) {
+        let mut senders = self.0.lock().unwrap();
+        senders.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
+
+/// State shared between the recorder and its main loop thread.
+#[derive(Default)]
+struct Shared {
+    /// The pipeline currently running, none while restarting.
+    pipeline: Mutex<Option<gst::Pipeline>>,
+    stopping: AtomicBool,
+    /// Index of the next segment, carried over to restarted pipelines and
+    /// later starts.
+    next_index: Arc<AtomicU32>,
+    /// Carried over to restarted pipelines.
+    pause: Mutex<PauseReasons>,
+    /// Bitrate set by [`CameraRecorder::set_bitrate`] in kbit/s, 0 for the
+    /// configured one.
+    bitrate: AtomicU32,
+    metrics: Arc<Metrics>,
+}
+
+impl Shared {
+    fn is_paused(&self) -> bool {
+        self.pause.lock().unwrap().is_paused()
+    }
+
+    /// Updates why recording is paused, pausing or resuming `pipeline`
+    /// if that changes whether it is.
+    fn update_pause<F>(&self, pipeline: Option<&gst::Pipeline>, update: F) -> Result<(), RecorderError>
+    where
+        F: FnOnce(&mut PauseReasons),
+    {
+        let mut reasons = self.pause.lock().unwrap();
+        match (pipeline, reasons.update(update)) {
+            (Some(pipeline), Some(true)) => pause::pause(pipeline),
+            (Some(pipeline), Some(false)) => pause::resume(pipeline, self.next_index.load(Ordering::SeqCst)),
+            _ => Ok(()),
+        }
+    }
+}
+
+struct Running {
+    shared: Arc<Shared>,
+    context: glib::MainContext,
+    main_loop: glib::MainLoop,
+    thread: thread::JoinHandle<()>,
+}
+
+/// Records a camera into segmented files on a background thread.
+///
+/// ```no_run
+/// use gst_camera_rs::{CameraRecorder, RecorderConfig, RecorderEvent};
+///
+/// let config = RecorderConfig::builder("/dev/video0", "video%05d.mp4").build();
+/// let recorder = CameraRecorder::new(config);
+/// let events = recorder.subscribe();
+/// recorder.start().unwrap();
+/// for event in events {
+///     if let RecorderEvent::Stopped = event {
+///         break;
+///     }
+/// }
+/// ```
+pub struct CameraRecorder {
+    config: RecorderConfig,
+    subscribers: Subscribers,
+    metrics: Arc<Metrics>,
+    next_index: Arc<AtomicU32>,
+    running: Mutex<Option<Running>>,
+}
+
+impl CameraRecorder {
+    pub fn new(config: RecorderConfig) -> CameraRecorder {
+        CameraRecorder {
+            metrics: Arc::new(Metrics::new(config.name.as_str())),
+            next_index: Arc::new(AtomicU32::new(0)),
+            config,
+            subscribers: Subscribers::default(),
+            running: Mutex::new(None),
+        }
+    }
+
+    pub fn config(&self) -> &RecorderConfig {
+        &self.config
+    }
+
+    /// Counters and gauges of this recorder, kept across starts.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// Returns a new receiver for every event emitted from now on.
+    pub fn subscribe(&self) -> mpsc::Receiver<RecorderEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.0.lock().unwrap().push(tx);
+        rx
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.lock().unwrap().as_ref().map(|r| !r.thread.is_finished()).unwrap_or(false)
+    }
+
+    pub fn status(&self) -> RecorderStatus {
+        let shared = self.shared().ok();
+        let pipeline = shared.as_ref().and_then(|s| s.pipeline.lock().unwrap().clone());
+        RecorderStatus {
+            running: shared.is_some(),
+            paused: shared.as_ref().map(|s| s.is_paused()).unwrap_or(false),
+            state: pipeline.as_ref().map(|p| p.get_state(gst::ClockTime::from_seconds(0)).1),
+            next_index: self.next_index.load(Ordering::SeqCst),
+            bitrate: pipeline
+                .and_then(|p| p.get_by_name("encoder"))
+                .and_then(|encoder| encoder::current_bitrate(&encoder)),
+        }
+    }
+
+    /// Builds the pipeline and starts recording on a dedicated main loop thread.
+    ///
+    /// Errors building the first pipeline are returned here, later ones are
+    /// handled by restarting according to the restart policy. A camera
+    /// that isn't plugged in is waited for.
+    pub fn start(&self) -> Result<(), RecorderError> {
+        let mut running = self.running.lock().unwrap();
+        if let Some(current) = running.as_ref() {
+            if !current.thread.is_finished() {
+                return Err(RecorderError::AlreadyRunning);
+            }
+        }
+        // it stopped on its own
+        if let Some(finished) = running.take() {
+            let _ = finished.thread.join();
+        }
+
+        self.config.validate()?;
+
+        // init gstreamer
+        gst::init()?;
+
+        // make room before the first new segment
+        enforce_retention(&self.config, &self.subscribers, false);
+
+        let shared = Arc::new(Shared {
+            next_index: self.next_index.clone(),
+            metrics: self.metrics.clone(),
+            ..Shared::default()
+        });
+
+        // init loop on its own context so several recorders can coexist
+        let context = glib::MainContext::new();
+        let main_loop = glib::MainLoop::new(Some(&context), false);
+
+        // a missing camera is waited for on the thread
+        let session = if self.config.source.device().map(devices::is_present).unwrap_or(true) {
+            let pipeline = pipeline::build(&self.config, 0)?;
+
+            // the watch must be attached while our context is the thread default
+            context.push_thread_default();
+            let session = Session::attach(pipeline, &context, &main_loop, &self.config, &self.subscribers, &shared);
+            context.pop_thread_default();
+            let session = session?;
+            *shared.pipeline.lock().unwrap() = Some(session.pipeline.clone());
+            Some(session)
+        } else {
+            None
+        };
+
+        let thread_shared = shared.clone();
+        let thread_context = context.clone();
+        let thread_loop = main_loop.clone();
+        let config = self.config.clone();
+        let subscribers = self.subscribers.clone();
+        let thread = thread::Builder::new()
+            .name(String::from("camera-recorder"))
+            .spawn(move || {
+                thread_context.push_thread_default();
+                supervise(session, &thread_context, &thread_loop, &config, &subscribers, &thread_shared);
+                thread_context.pop_thread_default();
+                subscribers.emit(RecorderEvent::Stopped);
+            })
+            .map_err(|e| RecorderError::pipeline(format!("Cannot spawn the recorder thread: {}", e)))?;
+
+        *running = Some(Running { shared, context, main_loop, thread });
+
+        Ok(())
+    }
+
+    /// Sends EOS so the current segment gets finalized, without waiting
+    /// for it.
+    ///
+    /// Should EOS not come out of the pipeline within the configured
+    /// shutdown timeout, the main loop is quit anyway and
+    /// [`RecorderEvent::ShutdownTimedOut`] emitted. Between restarts the
+    /// recorder stops right away. Safe to call from any thread, e.g. a
+    /// signal handler.
+    pub fn request_stop(&self) -> Result<(), RecorderError> {
+        let running = self.running.lock().unwrap();
+        let running = running.as_ref()
+            .filter(|r| !r.thread.is_finished())
+            .ok_or(RecorderError::NotRunning)?;
+
+        // the thread checks `stopping` under this lock before installing a
+        // new pipeline
+        let pipeline = running.shared.pipeline.lock().unwrap();
+        running.shared.stopping.store(true, Ordering::SeqCst);
+
+        let main_loop = running.main_loop.clone();
+        let source = match &*pipeline {
+            Some(pipeline) if !running.shared.is_paused() => {
+                pipeline.send_event(gst::Event::new_eos().build());
+
+                let subscribers = self.subscribers.clone();
+                let timeout = self.config.shutdown_timeout.mseconds().unwrap_or(0);
+                glib::timeout_source_new(timeout as u32, None, glib::PRIORITY_DEFAULT, move || {
+                    subscribers.emit(RecorderEvent::ShutdownTimedOut);
+                    main_loop.quit();
+                    glib::Continue(false)
+                })
+            }
+            // paused with the last segment finalized, or waiting to restart,
+            // a source rather than quitting directly so the wakeup isn't
+            // lost if the loop isn't running yet
+            _ => glib::timeout_source_new(0, None, glib::PRIORITY_DEFAULT, move || {
+                main_loop.quit();
+                glib::Continue(false)
+            }),
+        };
+        source.attach(Some(&running.context));
+
+        Ok(())
+    }
+
+    /// Sends EOS so the current segment gets finalized and waits for the
+    /// main loop thread to finish, see [`CameraRecorder::request_stop`].
+    pub fn stop(&self) -> Result<(), RecorderError> {
+        self.request_stop()?;
+        self.wait();
+
+        Ok(())
+    }
+
+    /// Drops frames in front of the encoder instead of recording them and
+    /// finalizes the current segment, the source keeps running. Holds
+    /// across pipeline restarts until [`CameraRecorder::resume`].
+    pub fn pause(&self) -> Result<(), RecorderError> {
+        self.set_paused(true)
+    }
+
+    /// Records again, into a new segment starting on a keyframe.
+    pub fn resume(&self) -> Result<(), RecorderError> {
+        self.set_paused(false)
+    }
+
+    fn set_paused(&self, paused: bool) -> Result<(), RecorderError> {
+        let shared = self.shared()?;
+        // under the lock so a pipeline being installed picks it up
+        let pipeline = shared.pipeline.lock().unwrap();
+        let mut changed = false;
+        shared.update_pause(pipeline.as_ref(), |reasons| {
+            changed = reasons.requested != paused;
+            reasons.requested = paused;
+        })?;
+
+        if changed {
+            self.subscribers.emit(if paused { RecorderEvent::Paused } else { RecorderEvent::Resumed });
+        }
+
+        Ok(())
+    }
+
+    /// Closes the current segment and starts the next one on a keyframe
+    /// requested right away.
+    pub fn split_now(&self) -> Result<(), RecorderError> {
+        split::split_now(&self.pipeline()?)
+    }
+
+    /// Changes the encoder bitrate, in kbit/s, of the running pipeline and
+    /// the ones restarted after it.
+    pub fn set_bitrate(&self, kbps: u32) -> Result<(), RecorderError> {
+        if self.config.passthrough {
+            return Err(InvalidSetting::new("encoder.bitrate", "passthrough recording has no encoder").into());
+        }
+        if kbps == 0 {
+            return Err(InvalidSetting::new("encoder.bitrate", "must be positive").into());
+        }
+
+        let shared = self.shared()?;
+        let pipeline = shared.pipeline.lock().unwrap();
+        shared.bitrate.store(kbps, Ordering::SeqCst);
+        if let Some(encoder) = pipeline.as_ref().and_then(|p| p.get_by_name("encoder")) {
+            self.config.encoder.kind.set_bitrate(&encoder, kbps)?;
+        }
+
+        Ok(())
+    }
+
+    /// Grabs the next frame of the running pipeline as JPEG.
+    pub fn snapshot(&self) -> Result<Vec<u8>, RecorderError> {
+        snapshot::grab(&self.pipeline()?, SNAPSHOT_TIMEOUT)
+    }
+
+    fn shared(&self) -> Result<Arc<Shared>, RecorderError> {
+        match &*self.running.lock().unwrap() {
+            Some(running) if !running.thread.is_finished() => Ok(running.shared.clone()),
+            _ => Err(RecorderError::NotRunning),
+        }
+    }
+
+    /// The pipeline currently running, `NotRunning` also while restarting.
+    fn pipeline(&self) -> Result<gst::Pipeline, RecorderError> {
+        self.shared()?.pipeline.lock().unwrap().clone().ok_or(RecorderError::NotRunning)
+    }
+
+    /// Blocks until the recorder stops on its own (EOS, or an error it
+    /// doesn't restart after).
+    pub fn wait(&self) {
+        let running = self.running.lock().unwrap().take();
+        if let Some(running) = running {
+            let _ = running.thread.join();
+        }
+    }
+}
+
+impl Drop for CameraRecorder {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+/// A pipeline with its bus watch and periodic checks attached.
+struct Session {
+    pipeline: gst::Pipeline,
+    storage_monitor: Option<glib::Source>,
+    slate_switch: Option<glib::Source>,
+    watchdog: Option<glib::Source>,
+    split_clock: Option<glib::Source>,
+    corruption_reporter: Option<glib::Source>,
+    metrics_sampler: glib::Source,
+    failed: Arc<AtomicBool>,
+}
+
+impl Session {
+    /// Must be called with `context` as the thread default.
+    fn attach(
+        pipeline: gst::Pipeline,
+        context: &glib::MainContext,
+        main_loop: &glib::MainLoop,
+        config: &RecorderConfig,
+        subscribers: &Subscribers,
+        shared: &Arc<Shared>,
+    ) -> Result<Session, RecorderError> {
+        let failed = Arc::new(AtomicBool::new(false));
+        let corruption = CorruptionMonitor::attach(&pipeline, &config.corruption)
+            .map(|monitor| Arc::new(Mutex::new(monitor)));
+        add_bus_watch(&pipeline, main_loop, config, subscribers, shared, &failed, corruption.clone())?;
+
+        let storage_monitor = if config.storage.is_enabled() {
+            let mut monitor = StorageMonitor::new(&pipeline, config, subscribers, shared);
+            let interval = config.storage.check_interval.as_millis() as u32;
+            let source = glib::timeout_source_new(interval, None, glib::PRIORITY_DEFAULT, move || {
+                monitor.check();
+                glib::Continue(true)
+            });
+            source.attach(Some(context));
+            Some(source)
+        } else {
+            None
+        };
+
+        let slate_switch = if config.slate.enabled {
+            SlateSwitch::attach(&pipeline, config.slate.timeout).map(|mut switch| {
+                let subscribers = subscribers.clone();
+                let interval = config.slate.check_interval().as_millis() as u32;
+                let source = glib::timeout_source_new(interval, None, glib::PRIORITY_DEFAULT, move || {
+                    match switch.check() {
+                        Some(true) => subscribers.emit(RecorderEvent::SignalLost),
+                        Some(false) => subscribers.emit(RecorderEvent::SignalRestored),
+                        None => (),
+                    }
+                    glib::Continue(true)
+                });
+                source.attach(Some(context));
+                source
+            })
+        } else {
+            None
+        };
+
+        let watchdog = if config.watchdog.enabled {
+            Watchdog::attach(&pipeline, config.watchdog.timeout).map(|mut watchdog| {
+                let subscribers = subscribers.clone();
+                let main_loop = main_loop.clone();
+                let failed = failed.clone();
+                let restart = config.watchdog.restart;
+                let interval = config.watchdog.check_interval().as_millis() as u32;
+                let source = glib::timeout_source_new(interval, None, glib::PRIORITY_DEFAULT, move || {
+                    match watchdog.check() {
+                        Some(Some(since)) => {
+                            subscribers.emit(RecorderEvent::FrameStall { since });
+                            // handled like an error, the supervisor restarts it
+                            if restart {
+                                failed.store(true, Ordering::SeqCst);
+                                main_loop.quit();
+                                return glib::Continue(false);
+                            }
+                        }
+                        Some(None) => subscribers.emit(RecorderEvent::FramesResumed),
+                        None => (),
+                    }
+                    glib::Continue(true)
+                });
+                source.attach(Some(context));
+                source
+            })
+        } else {
+            None
+        };
+
+        let split_clock = if config.align_splits {
+            let mut clock = SplitClock::new(Duration::from_nanos(config.max_size_time.nseconds().unwrap_or(0)));
+            let pipeline = pipeline.clone();
+            let shared = shared.clone();
+            let interval = split::CHECK_INTERVAL.as_millis() as u32;
+            let source = glib::timeout_source_new(interval, None, glib::PRIORITY_DEFAULT, move || {
+                // resuming starts a new segment anyway
+                if clock.crossed() && !shared.is_paused() {
+                    if let Err(err) = split::split_now(&pipeline) {
+                        warn!("Cannot split the segment: {}", err);
+                    }
+                }
+                glib::Continue(true)
+            });
+            source.attach(Some(context));
+            Some(source)
+        } else {
+            None
+        };
+
+        // corrupt frames held back by the report interval
+        let corruption_reporter = corruption.map(|monitor| {
+            let subscribers = subscribers.clone();
+            let interval = config.corruption.report_interval.max(Duration::from_millis(100)).as_millis() as u32;
+            let source = glib::timeout_source_new(interval, None, glib::PRIORITY_DEFAULT, move || {
+                if let Some(dropped) = monitor.lock().unwrap().flush() {
+                    subscribers.emit(RecorderEvent::CorruptFrames { dropped });
+                }
+                glib::Continue(true)
+            });
+            source.attach(Some(context));
+            source
+        });
+
+        shared.metrics.attach(&pipeline);
+        let mut sampler = MetricsSampler::new(&shared.metrics, &pipeline);
+        let metrics_sampler = glib::timeout_source_new(1000, None, glib::PRIORITY_DEFAULT, move || {
+            sampler.sample();
+            glib::Continue(true)
+        });
+        metrics_sampler.attach(Some(context));
+
+        Ok(Session {
+            pipeline,
+            storage_monitor,
+            slate_switch,
+            watchdog,
+            split_clock,
+            corruption_reporter,
+            metrics_sampler,
+            failed,
+        })
+    }
+
+    /// Applies the runtime controls in `shared`, with its pipeline lock held
+    /// so none gets lost.
+    fn apply_controls(&self, config: &RecorderConfig, shared: &Shared) -> Result<(), RecorderError> {
+        // not playing yet, there is no segment to finalize
+        if shared.is_paused() {
+            if let Some(valve) = self.pipeline.get_by_name("record_valve") {
+                valve.set_property("drop", &true)?;
+            }
+        }
+        let bitrate = shared.bitrate.load(Ordering::SeqCst);
+        if bitrate > 0 {
+            if let Some(encoder) = self.pipeline.get_by_name("encoder") {
+                config.encoder.kind.set_bitrate(&encoder, bitrate)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Plays the pipeline until the main loop quits, returns whether it
+    /// failed.
+    fn run(&self, main_loop: &glib::MainLoop, subscribers: &Subscribers, metrics: &Metrics) -> bool {
+        match self.pipeline.set_state(gst::State::Playing) {
+            Ok(_) => {
+                main_loop.run();
+                self.failed.load(Ordering::SeqCst)
+            }
+            Err(err) => {
+                metrics.error();
+                subscribers.emit(RecorderEvent::PipelineFailed(err.to_string()));
+                true
+            }
+        }
+    }
+
+    fn teardown(self, shared: &Shared) {
+        *shared.pipeline.lock().unwrap() = None;
+
+        if let Some(storage_monitor) = self.storage_monitor {
+            storage_monitor.destroy();
+        }
+        if let Some(slate_switch) = self.slate_switch {
+            slate_switch.destroy();
+        }
+        if let Some(watchdog) = self.watchdog {
+            watchdog.destroy();
+        }
+        if let Some(split_clock) = self.split_clock {
+            split_clock.destroy();
+        }
+        if let Some(corruption_reporter) = self.corruption_reporter {
+            corruption_reporter.destroy();
+        }
+        self.metrics_sampler.destroy();
+        let _ = self.pipeline.set_state(gst::State::Null);
+        if let Some(bus) = self.pipeline.get_bus() {
+            let _ = bus.remove_watch();
+        }
+    }
+}
+
+/// Runs `session`, then rebuilds the pipeline after errors as long as the
+/// restart policy allows, or once the camera is back after being unplugged.
+/// Runs on the recorder thread, with `context` as its thread default.
+fn supervise(
+    session: Option<Session>,
+    context: &glib::MainContext,
+    main_loop: &glib::MainLoop,
+    config: &RecorderConfig,
+    subscribers: &Subscribers,
+    shared: &Arc<Shared>,
+) {
+    let mut supervisor = Supervisor::new(config.restart.clone());
+    let mut session = session;
+    let mut ran_for = Duration::from_secs(0);
+
+    loop {
+        if let Some(current) = session.take() {
+            let started = Instant::now();
+            let failed = current.run(main_loop, subscribers, &shared.metrics);
+            current.teardown(shared);
+            if !failed || shared.stopping.load(Ordering::SeqCst) {
+                break;
+            }
+            ran_for = started.elapsed();
+        }
+
+        match config.source.device().filter(|device| !devices::is_present(device)) {
+            // unplugged, restarting as soon as it's back doesn't count as a
+            // restart
+            Some(device) => {
+                subscribers.emit(RecorderEvent::DeviceOffline { device: device.into() });
+                if !wait_for_device(device, context, main_loop, shared) {
+                    break;
+                }
+                subscribers.emit(RecorderEvent::DeviceOnline { device: device.into() });
+            }
+            None => {
+                let delay = match supervisor.next_restart(ran_for) {
+                    Some(delay) => delay,
+                    None => {
+                        if config.restart.enabled {
+                            subscribers.emit(RecorderEvent::RestartLimitReached);
+                        }
+                        break;
+                    }
+                };
+                shared.metrics.restarted();
+                subscribers.emit(RecorderEvent::Restarting { attempt: supervisor.attempt(), delay });
+
+                // wait on the main loop so stopping can cut the backoff short
+                let quit_loop = main_loop.clone();
+                let backoff = glib::timeout_source_new(
+                    delay.as_millis() as u32,
+                    None,
+                    glib::PRIORITY_DEFAULT,
+                    move || {
+                        quit_loop.quit();
+                        glib::Continue(false)
+                    },
+                );
+                backoff.attach(Some(context));
+                main_loop.run();
+                backoff.destroy();
+            }
+        }
+        if shared.stopping.load(Ordering::SeqCst) {
+            break;
+        }
+
+        let built = pipeline::build(config, shared.next_index.load(Ordering::SeqCst))
+            .and_then(|pipeline| Session::attach(pipeline, context, main_loop, config, subscribers, shared));
+        match built {
+            Ok(new_session) => {
+                let mut pipeline = shared.pipeline.lock().unwrap();
+                if shared.stopping.load(Ordering::SeqCst) {
+                    drop(pipeline);
+                    new_session.teardown(shared);
+                    break;
+                }
+                match new_session.apply_controls(config, shared) {
+                    Ok(()) => {
+                        *pipeline = Some(new_session.pipeline.clone());
+                        session = Some(new_session);
+                    }
+                    Err(err) => {
+                        drop(pipeline);
+                        new_session.teardown(shared);
+                        shared.metrics.error();
+                        subscribers.emit(RecorderEvent::PipelineFailed(err.to_string()));
+                        ran_for = Duration::from_secs(0);
+                    }
+                }
+            }
+            Err(err) => {
+                shared.metrics.error();
+                subscribers.emit(RecorderEvent::PipelineFailed(err.to_string()));
+                ran_for = Duration::from_secs(0);
+            }
+        }
+        if shared.stopping.load(Ordering::SeqCst) && session.is_none() {
+            break;
+        }
+    }
+}
+
+/// Runs the main loop until `device` shows up, returns false if the
+/// recorder got stopped meanwhile.
+fn wait_for_device(
+    device: &str,
+    context: &glib::MainContext,
+    main_loop: &glib::MainLoop,
+    shared: &Shared,
+) -> bool {
+    let monitor = gst::DeviceMonitor::new();
+    monitor.add_filter("Video/Source", None);
+
+    // the running monitor also resolves serial numbers, rather than one
+    // started for every check
+    let watch = monitor.get_bus().add_watch({
+        let device = device.to_string();
+        let main_loop = main_loop.clone();
+        let monitor = monitor.clone();
+        move |_, msg| {
+            if let gst::MessageView::DeviceAdded(..) = msg.view() {
+                if devices::is_present_in(&monitor, &device) {
+                    main_loop.quit();
+                }
+            }
+            glib::Continue(true)
+        }
+    });
+    let monitoring = watch.is_some() && monitor.start().is_ok();
+
+    // it may have come back before the monitor started, and without a
+    // monitor all we can do is poll
+    let poll = {
+        let device = device.to_string();
+        let main_loop = main_loop.clone();
+        let monitor = monitor.clone();
+        glib::timeout_source_new(if monitoring { 0 } else { 1000 }, None, glib::PRIORITY_DEFAULT, move || {
+            let present = if monitoring {
+                devices::is_present_in(&monitor, &device)
+            } else {
+                devices::is_present(&device)
+            };
+            if present {
+                main_loop.quit();
+            }
+            glib::Continue(!monitoring)
+        })
+    };
+    poll.attach(Some(context));
+
+    main_loop.run();
+
+    poll.destroy();
+    if monitoring {
+        monitor.stop();
+    }
+    let _ = monitor.get_bus().remove_watch();
+
+    !shared.stopping.load(Ordering::SeqCst)
+}
+
+/// Applies the storage policy to the free space of the output filesystem
+/// every time it is checked.
+struct StorageMonitor {
+    pipeline: gst::Pipeline,
+    config: RecorderConfig,
+    subscribers: Subscribers,
+    shared: Arc<Shared>,
+    /// Encoder bitrate to restore in kbit/s, set while lowered.
+    saved_bitrate: Option<u32>,
+    full: bool,
+}
+
+impl StorageMonitor {
+    fn new(
+        pipeline: &gst::Pipeline,
+        config: &RecorderConfig,
+        subscribers: &Subscribers,
+        shared: &Arc<Shared>,
+    ) -> StorageMonitor {
+        StorageMonitor {
+            pipeline: pipeline.clone(),
+            config: config.clone(),
+            subscribers: subscribers.clone(),
+            shared: shared.clone(),
+            saved_bitrate: None,
+            full: false,
+        }
+    }
+
+    fn check(&mut self) {
+        if let Err(err) = self.try_check() {
+            self.subscribers.emit(RecorderEvent::StorageFailed(err.to_string()));
+        }
+    }
+
+    fn try_check(&mut self) -> Result<(), RecorderError> {
+        let config = &self.config;
+        let policy = &config.storage;
+        let dir = retention::segments_dir(&config.location, &config.name)?;
+        let mut free = storage::free_space(&dir)?;
+
+        if let Some(target) = policy.purge_below {
+            if free < target {
+                let deleted = storage::purge(&config.location, &config.name, target)?;
+                if !deleted.is_empty() {
+                    self.subscribers.emit(RecorderEvent::LowSpace { free, action: LowSpaceAction::Purge });
+                    for segment in deleted {
+                        self.subscribers.emit(RecorderEvent::SegmentDeleted(segment.path));
+                    }
+                    free = storage::free_space(&dir)?;
+                }
+            }
+        }
+
+        if let (Some(threshold), Some(low_bitrate)) = (policy.lower_bitrate_below, policy.low_bitrate) {
+            if let Some(encoder) = self.pipeline.get_by_name("encoder") {
+                if free < threshold && self.saved_bitrate.is_none() {
+                    self.saved_bitrate = encoder::current_bitrate(&encoder)
+                        .or(config.encoder.bitrate)
+                        .or(Some(low_bitrate));
+                    config.encoder.kind.set_bitrate(&encoder, low_bitrate)?;
+                    self.subscribers.emit(RecorderEvent::LowSpace { free, action: LowSpaceAction::LowerBitrate });
+                } else if free >= threshold {
+                    if let Some(bitrate) = self.saved_bitrate.take() {
+                        config.encoder.kind.set_bitrate(&encoder, bitrate)?;
+                        self.subscribers.emit(RecorderEvent::SpaceRecovered {
+                            free,
+                            action: LowSpaceAction::LowerBitrate,
+                        });
+                    }
+                }
+            }
+        }
+
+        if let Some(threshold) = policy.pause_below {
+            let low_space = free < threshold;
+            if low_space != self.shared.pause.lock().unwrap().low_space {
+                self.shared.update_pause(Some(&self.pipeline), |reasons| reasons.low_space = low_space)?;
+                if low_space {
+                    self.subscribers.emit(RecorderEvent::LowSpace { free, action: LowSpaceAction::Pause });
+                } else {
+                    self.subscribers.emit(RecorderEvent::SpaceRecovered { free, action: LowSpaceAction::Pause });
+                }
+            }
+        }
+
+        // without pausing, recording goes on until the sink fails
+        let full = policy.pause_below.is_none() && policy.min_free().map(|min| free < min).unwrap_or(false);
+        if full && !self.full {
+            self.subscribers.emit(RecorderEvent::DiskFull { free });
+        }
+        self.full = full;
+
+        Ok(())
+    }
+}
+
+/// Applies the retention policy, reporting what got deleted.
+fn enforce_retention(config: &RecorderConfig, subscribers: &Subscribers, keep_newest: bool) {
+    match config.retention.enforce(&config.location, &config.name, keep_newest) {
+        Ok(deleted) => {
+            for segment in deleted {
+                subscribers.emit(RecorderEvent::SegmentDeleted(segment.path));
+            }
+        }
+        Err(err) => subscribers.emit(RecorderEvent::RetentionFailed(err.to_string())),
+    }
+}
+
+fn add_bus_watch(
+    pipeline: &gst::Pipeline,
+    main_loop: &glib::MainLoop,
+    config: &RecorderConfig,
+    subscribers: &Subscribers,
+    shared: &Arc<Shared>,
+    failed: &Arc<AtomicBool>,
+    corruption: Option<Arc<Mutex<CorruptionMonitor>>>,
+) -> Result<glib::SourceId, RecorderError> {
+    let bus: gst::Bus = pipeline.get_bus().ok_or(RecorderError::Watch)?;
+    let loop_clone = main_loop.clone();
+    let config = config.clone();
+    let subscribers = subscribers.clone();
+    let shared = shared.clone();
+    let failed = failed.clone();
+    let pipeline_name = String::from(pipeline.get_name());
+    let bus_watch_id = bus.add_watch(move |_, msg| {
+        use gst::MessageView;
+
+        trace!("Got {:?}", msg);
+
+        match msg.view() {
+            // the segment got finalized for pausing
+            MessageView::Eos(..) if shared.is_paused() && !shared.stopping.load(Ordering::SeqCst) => {
+                debug!("Finalized the segment, recording paused");
+            }
+            MessageView::Eos(..) => {
+                subscribers.emit(RecorderEvent::Eos);
+                loop_clone.quit();
+            }
+            MessageView::Error(err) => {
+                if err.get_error().kind::<gst::ResourceError>() == Some(gst::ResourceError::NoSpaceLeft) {
+                    let free = retention::segments_dir(&config.location, &config.name)
+                        .and_then(|dir| storage::free_space(&dir))
+                        .unwrap_or(0);
+                    subscribers.emit(RecorderEvent::DiskFull { free });
+                }
+
+                let error_msg = ErrorMessage::new(
+                    msg.get_src(),
+                    err.get_error(),
+                    err.get_debug().map(|d| d.to_string()),
+                );
+
+                shared.metrics.error();
+                subscribers.emit(RecorderEvent::Error(error_msg));
+                failed.store(true, Ordering::SeqCst);
+                loop_clone.quit();
+            }
+            MessageView::Warning(w) if corruption.is_some()
+                && CorruptionMonitor::is_corrupt_frame(msg.get_src().as_ref(), &w.get_error()) =>
+            {
+                shared.metrics.corrupt_frame();
+                let verdict = corruption.as_ref().map(|monitor| monitor.lock().unwrap().record()).unwrap_or_default();
+                if let Some(dropped) = verdict.report {
+                    subscribers.emit(RecorderEvent::CorruptFrames { dropped });
+                }
+                if let Some((corrupt, frames)) = verdict.exceeded {
+                    shared.metrics.error();
+                    subscribers.emit(RecorderEvent::TooManyCorruptFrames { corrupt, frames });
+                    failed.store(true, Ordering::SeqCst);
+                    loop_clone.quit();
+                }
+            }
+            MessageView::Warning(w) => {
+                let error_msg = ErrorMessage::new(
+                    msg.get_src(),
+                    w.get_error(),
+                    w.get_debug().map(|d| d.to_string()),
+                );
+
+                subscribers.emit(RecorderEvent::Warning(error_msg));
+            }
+            MessageView::Qos(q) => {
+                if let (Some(src), gst::GenericFormattedValue::Buffers(dropped)) = (msg.get_src(), q.get_stats().1) {
+                    shared.metrics.qos(&src.get_path_string(), dropped.0.unwrap_or(0));
+                }
+            }
+            MessageView::StateChanged(s) => {
+                let src = s.get_src();
+                let is_pipeline = src.as_ref().map(|s| s.get_name().as_str() == pipeline_name).unwrap_or(false);
+                if is_pipeline {
+                    debug!("Pipeline state changed: {:?} -> {:?}", s.get_old(), s.get_current());
+                }
+
+                subscribers.emit(RecorderEvent::StateChanged {
+                    src: src.map(|s| String::from(s.get_path_string())),
+                    old: s.get_old(),
+                    current: s.get_current(),
+                    pending: s.get_pending(),
+                });
+
+                if is_pipeline && s.get_current() == gst::State::Playing {
+                    subscribers.emit(RecorderEvent::Started);
+                }
+            }
+            MessageView::Element(e) => match e.get_structure() {
+                Some(s) if s.get_name() == "splitmuxsink-fragment-opened" => {
+                    shared.next_index.fetch_add(1, Ordering::SeqCst);
+                }
+                Some(s) if s.get_name() == "splitmuxsink-fragment-closed" => {
+                    if let Some(location) = s.get::<String>("location") {
+                        let size = fs::metadata(&location).map(|m| m.len()).unwrap_or(0);
+                        shared.metrics.segment_written(size);
+                        subscribers.emit(RecorderEvent::SegmentClosed { location });
+                    }
+                    // the next segment may already be open, spare it
+                    enforce_retention(&config, &subscribers, true);
+                }
+                _ => (),
+            },
+            _ => (),
+        }
+
+        glib::Continue(true)
+    })
+        .ok_or(RecorderError::Watch)?;
+
+    Ok(bus_watch_id)
+}
@@ -0,0 +1,288 @@
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::error::{InvalidSetting, RecorderError};
+use crate::naming::{self, wildcard_match};
+
+/// Limits on the recorded segments, the oldest ones being deleted once any
+/// of them is exceeded. Unset limits don't apply.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Total size of all segments in bytes.
+    pub max_bytes: Option<u64>,
+    pub max_files: Option<usize>,
+    /// Segments last modified longer ago are deleted.
+    pub max_age: Option<Duration>,
+}
+
+impl RetentionPolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.max_bytes.is_some() || self.max_files.is_some() || self.max_age.is_some()
+    }
+
+    pub fn validate(&self) -> Result<(), InvalidSetting> {
+        if self.max_bytes == Some(0) {
+            return Err(InvalidSetting::new("retention.max_bytes", "must be greater than 0"));
+        }
+        if self.max_files == Some(0) {
+            return Err(InvalidSetting::new("retention.max_files", "must be greater than 0"));
+        }
+        if self.max_age == Some(Duration::from_secs(0)) {
+            return Err(InvalidSetting::new("retention.max_age", "must be greater than 0"));
+        }
+        Ok(())
+    }
+
+    /// Deletes the oldest segments written to `location` until the policy
+    /// holds again and returns the deleted ones.
+    ///
+    /// With `keep_newest` the newest segment, which is still being written
+    /// while recording, is never deleted though it counts towards the
+    /// limits.
+    pub fn enforce(&self, location: &str, camera: &str, keep_newest: bool) -> Result<Vec<Segment>, RecorderError> {
+        if !self.is_enabled() {
+            return Ok(Vec::new());
+        }
+
+        let mut segments = find_segments(location, camera)?;
+        let mut total_bytes = segments.iter().map(|s| s.size).sum::<u64>();
+        let mut count = segments.len();
+        if keep_newest {
+            segments.pop();
+        }
+
+        let now = SystemTime::now();
+        let mut deleted = Vec::new();
+        for segment in segments {
+            let too_many = self.max_files.map(|max| count > max).unwrap_or(false);
+            let too_big = self.max_bytes.map(|max| total_bytes > max).unwrap_or(false);
+            let too_old = self.max_age
+                .map(|max| now.duration_since(segment.modified).map(|age| age > max).unwrap_or(false))
+                .unwrap_or(false);
+            // segments are sorted oldest first, the newer ones are fine too
+            if !too_many && !too_big && !too_old {
+                break;
+            }
+
+            match fs::remove_file(&segment.path) {
+                Ok(()) => (),
+                // deleted behind our back, doesn't count anymore either way
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+                Err(e) => return Err(RecorderError::Storage(e)),
+            }
+            count -= 1;
+            total_bytes -= segment.size;
+            deleted.push(segment);
+        }
+
+        Ok(deleted)
+    }
+}
+
+/// A segment file on disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// Every existing segment written to `location` by `camera`, oldest first.
+pub fn find_segments(location: &str, camera: &str) -> Result<Vec<Segment>, RecorderError> {
+    let (base, patterns) = split_glob(&naming::segment_glob(location, camera)?);
+
+    let mut segments = Vec::new();
+    collect(&base, &patterns, &mut segments)?;
+    segments.sort_by(|a, b| a.modified.cmp(&b.modified).then_with(|| a.path.cmp(&b.path)));
+
+    Ok(segments)
+}
+
+/// Directory holding every segment written to `location` by `camera`: the
+/// deepest one not depending on the segment.
+pub fn segments_dir(location: &str, camera: &str) -> Result<PathBuf, RecorderError> {
+    Ok(split_glob(&naming::segment_glob(location, camera)?).0)
+}
+
+/// Splits `glob` into its deepest directory without wildcards and the
+/// patterns of the path components below it.
+fn split_glob(glob: &str) -> (PathBuf, Vec<String>) {
+    let mut base = PathBuf::new();
+    let mut patterns = Vec::new();
+    for component in Path::new(glob).components() {
+        match component {
+            Component::Normal(part) if patterns.is_empty() && !part.to_string_lossy().contains('*') => {
+                base.push(part)
+            }
+            Component::Normal(part) => patterns.push(part.to_string_lossy().into_owned()),
+            other => base.push(other.as_os_str()),
+        }
+    }
+    // the file name is matched as a pattern even without wildcards
+    if patterns.is_empty() {
+        if let Some(file_name) = base.file_name().map(|name| name.to_string_lossy().into_owned()) {
+            patterns.push(file_name);
+            base.pop();
+        }
+    }
+    if base.as_os_str().is_empty() {
+        base.push(".");
+    }
+
+    (base, patterns)
+}
+
+fn collect(dir: &Path, patterns: &[String], segments: &mut Vec<Segment>) -> Result<(), RecorderError> {
+    let (pattern, rest) = match patterns.split_first() {
+        Some(split) => split,
+        None => return Ok(()),
+    };
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(RecorderError::Storage(e)),
+    };
+
+    for entry in entries {
+        let entry = entry.map_err(RecorderError::Storage)?;
+        if !wildcard_match(pattern, &entry.file_name().to_string_lossy()) {
+            continue;
+        }
+        let metadata = entry.metadata().map_err(RecorderError::Storage)?;
+        if rest.is_empty() && metadata.is_file() {
+            segments.push(Segment {
+                path: entry.path(),
+                size: metadata.len(),
+                modified: metadata.modified().map_err(RecorderError::Storage)?,
+            });
+        } else if !rest.is_empty() && metadata.is_dir() {
+            collect(&entry.path(), rest, segments)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::File;
+
+    use crate::testutil::temp_dir;
+
+    /// Writes `size` bytes to `name` in `dir`, last modified `age` ago.
+    fn segment(dir: &Path, name: &str, size: usize, age: Duration) -> PathBuf {
+        let path = dir.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(&path, vec![0u8; size]).unwrap();
+        File::options().write(true).open(&path).unwrap().set_modified(SystemTime::now() - age).unwrap();
+        path
+    }
+
+    /// Segments `video00000.mp4` to `video0000<count - 1>.mp4`, the first
+    /// one the oldest, plus a file that isn't one.
+    fn segments(dir: &Path, count: u64) -> Vec<PathBuf> {
+        segment(dir, "notes.txt", 1000, Duration::from_secs(1000));
+        (0..count)
+            .map(|i| segment(dir, &format!("video{:05}.mp4", i), 10, Duration::from_secs(100 * (count - i))))
+            .collect()
+    }
+
+    fn location(dir: &Path) -> String {
+        format!("{}/video%05d.mp4", dir.display())
+    }
+
+    fn paths_of(segments: Vec<Segment>) -> Vec<PathBuf> {
+        segments.into_iter().map(|segment| segment.path).collect()
+    }
+
+    #[test]
+    fn finds_segments_oldest_first() {
+        let dir = temp_dir("retention-find");
+        let paths = segments(&dir, 3);
+        segment(&dir, "front/00001.mkv", 10, Duration::from_secs(50));
+
+        let found = find_segments(&location(&dir), "front").unwrap();
+        assert_eq!(paths_of(found), paths);
+
+        let template = format!("{}/{{camera}}/{{seq}}.mkv", dir.display());
+        let found = find_segments(&template, "front").unwrap();
+        assert_eq!(paths_of(found), vec![dir.join("front/00001.mkv")]);
+        assert_eq!(segments_dir(&template, "front").unwrap(), dir.join("front"));
+
+        let dated = segment(&dir, "front/2024/01/02/00002.mkv", 10, Duration::from_secs(40));
+        let template = format!("{}/{{camera}}/{{time:%Y/%m/%d}}/{{seq}}.mkv", dir.display());
+        let found = find_segments(&template, "front").unwrap();
+        assert_eq!(paths_of(found), vec![dated]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn deletes_the_oldest_beyond_max_files() {
+        let dir = temp_dir("retention-files");
+        let paths = segments(&dir, 5);
+        let policy = RetentionPolicy { max_files: Some(3), ..RetentionPolicy::default() };
+
+        let deleted = policy.enforce(&location(&dir), "front", false).unwrap();
+        assert_eq!(paths_of(deleted), paths[..2]);
+        assert!(paths[2..].iter().all(|path| path.exists()));
+        assert!(dir.join("notes.txt").exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn deletes_the_oldest_beyond_max_bytes() {
+        let dir = temp_dir("retention-bytes");
+        let paths = segments(&dir, 4);
+        let policy = RetentionPolicy { max_bytes: Some(25), ..RetentionPolicy::default() };
+
+        let deleted = policy.enforce(&location(&dir), "front", false).unwrap();
+        assert_eq!(paths_of(deleted), paths[..2]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn deletes_segments_older_than_max_age() {
+        let dir = temp_dir("retention-age");
+        let paths = segments(&dir, 4);
+        let policy = RetentionPolicy { max_age: Some(Duration::from_secs(250)), ..RetentionPolicy::default() };
+
+        let deleted = policy.enforce(&location(&dir), "front", false).unwrap();
+        assert_eq!(paths_of(deleted), paths[..2]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn keeps_the_newest_segment() {
+        let dir = temp_dir("retention-newest");
+        let paths = segments(&dir, 3);
+        let policy = RetentionPolicy { max_age: Some(Duration::from_secs(1)), ..RetentionPolicy::default() };
+
+        let deleted = policy.enforce(&location(&dir), "front", true).unwrap();
+        assert_eq!(paths_of(deleted), paths[..2]);
+        assert!(paths[2].exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn deletes_nothing_without_limits() {
+        let dir = temp_dir("retention-disabled");
+        let paths = segments(&dir, 3);
+
+        assert!(RetentionPolicy::default().enforce(&location(&dir), "front", false).unwrap().is_empty());
+        assert!(paths.iter().all(|path| path.exists()));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
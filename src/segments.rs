@@ -0,0 +1,121 @@
+use gstreamer as gst;
+use gst::prelude::*;
+
+use std::path::Path;
+
+use crate::container::Container;
+use crate::encoder::Encoder;
+use crate::error::{InvalidSetting, RecorderError};
+use crate::pipeline::{make_element, run_to_eos};
+
+/// Decodes `path` completely to check that the segment is playable.
+pub fn verify(path: &Path) -> Result<(), RecorderError> {
+    gst::init()?;
+
+    let pipeline = gst::Pipeline::new("segment-verify");
+
+    let filesrc = make_element("filesrc", "filesrc")?;
+    filesrc.set_property("location", &path.to_string_lossy().as_ref())?;
+
+    let decodebin = make_element("decodebin", "decodebin")?;
+
+    pipeline.add_many(&[&filesrc, &decodebin])?;
+    filesrc.link(&decodebin)?;
+
+    // every decoded stream ends up in its own fakesink
+    decodebin.connect_pad_added(|decodebin, src_pad| {
+        let bin = match decodebin.get_parent().and_then(|p| p.downcast::<gst::Bin>().ok()) {
+            Some(bin) => bin,
+            None => return,
+        };
+        let fakesink = match gst::ElementFactory::make("fakesink", None) {
+            Some(fakesink) => fakesink,
+            None => return,
+        };
+        let _ = fakesink.set_property("sync", &false);
+
+        if bin.add(&fakesink).is_err() {
+            return;
+        }
+        if let Some(sink_pad) = fakesink.get_static_pad("sink") {
+            let _ = src_pad.link(&sink_pad);
+        }
+        let _ = fakesink.sync_state_with_parent();
+    });
+
+    run_to_eos(&pipeline)
+}
+
+/// Remuxes all segments matching the glob `pattern` into a single file,
+/// in the container the extension of `output` stands for.
+pub fn export(pattern: &str, output: &Path) -> Result<(), RecorderError> {
+    gst::init()?;
+
+    let location = output.to_string_lossy();
+    let container = Container::from_location(&location).ok_or_else(|| {
+        InvalidSetting::new("output", format!("cannot tell the container from the extension of {}", location))
+    })?;
+
+    let pipeline = gst::Pipeline::new("segment-export");
+
+    let splitmuxsrc = make_element("splitmuxsrc", "splitmuxsrc")?;
+    splitmuxsrc.set_property("location", &pattern)?;
+
+    let muxer = make_element(container.muxer_name(), "muxer")?;
+
+    let filesink = make_element("filesink", "filesink")?;
+    filesink.set_property("location", &location.as_ref())?;
+
+    pipeline.add_many(&[&splitmuxsrc, &muxer, &filesink])?;
+    muxer.link(&filesink)?;
+
+    // splitmuxsrc only exposes its pads once the first segment is opened,
+    // which tells the video format
+    splitmuxsrc.connect_pad_added(move |splitmuxsrc, src_pad| {
+        if !src_pad.get_name().starts_with("video") || src_pad.is_linked() {
+            return;
+        }
+        if let Err(err) = link_video(splitmuxsrc, src_pad, &muxer, container) {
+            gst::gst_element_error!(splitmuxsrc, gst::StreamError::Format, (&err.to_string()));
+        }
+    });
+
+    run_to_eos(&pipeline)
+}
+
+/// Links the video `src_pad` of `splitmuxsrc` to `muxer`, through the
+/// parser its format needs.
+fn link_video(
+    splitmuxsrc: &gst::Element,
+    src_pad: &gst::Pad,
+    muxer: &gst::Element,
+    container: Container,
+) -> Result<(), RecorderError> {
+    let caps = src_pad.get_current_caps()
+        .or_else(|| src_pad.query_caps(None))
+        .ok_or_else(|| RecorderError::pipeline("the segments have no video caps"))?;
+    let name = caps.get_structure(0).map(|s| s.get_name().to_string()).unwrap_or_default();
+    let format = Encoder::from_caps_name(&name)
+        .ok_or(RecorderError::UnknownValue { kind: "video format", value: name })?;
+    if !container.supports(format) {
+        return Err(InvalidSetting::new("output", format!("{} cannot hold {} video", container, format)).into());
+    }
+
+    let sink_pad = match format.parser() {
+        Some(parser) => {
+            let bin = splitmuxsrc.get_parent()
+                .and_then(|parent| parent.downcast::<gst::Bin>().ok())
+                .ok_or_else(|| RecorderError::pipeline("splitmuxsrc is not in a pipeline"))?;
+            let parser = make_element(parser, None)?;
+            bin.add(&parser)?;
+            parser.link(muxer)?;
+            parser.sync_state_with_parent()?;
+            parser.get_static_pad("sink")
+        }
+        None => muxer.get_compatible_pad(src_pad, None),
+    };
+    let sink_pad = sink_pad.ok_or_else(|| RecorderError::pipeline(format!("{} takes no {} video", container, format)))?;
+    src_pad.link(&sink_pad)?;
+
+    Ok(())
+}
@@ -0,0 +1,697 @@
+//! Recorder settings layered from a TOML file, the environment and the
+//! command line.
+//!
+//! A settings file holds shared `[defaults]` and one section per camera:
+//!
+//! ```toml
+//! [defaults.encoder]
+//! kind = "x265"
+//! bitrate = 4000
+//! speed_preset = "veryfast"
+//! tune = "zerolatency"
+//!
+//! [cameras.front]
+//! device = "/dev/video0"
+//!
+//! [cameras.test.source]
+//! kind = "test"
+//! pattern = "ball"
+//!
+//! [cameras.front.video]
+//! width = 1920
+//! height = 1080
+//!
+//! [cameras.front.output]
+//! location = "/srv/recordings/front%05d.mkv"
+//! container = "matroska"
+//! streamable = true
+//!
+//! [cameras.front.retention]
+//! max_bytes = 50_000_000_000
+//! max_age = 604800
+//!
+//! [cameras.front.storage]
+//! purge_below = 10_000_000_000
+//! pause_below = 1_000_000_000
+//!
+//! [defaults.restart]
+//! enabled = true
+//! max_backoff = 30
+//!
+//! [defaults.watchdog]
+//! enabled = true
+//! timeout = 5000
+//! restart = true
+//!
+//! [cameras.front.corruption]
+//! max_percent = 20
+//! window = 30
+//! ```
+//!
+//! Every key can then be overridden by an environment variable, e.g.
+//! `GST_CAMERA_ENCODER_KEY_INT_MAX=30` for `encoder.key_int_max`, and by
+//! [`SettingsLoader::set`], which the CLI uses for its flags.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use gstreamer as gst;
+
+use serde::Deserialize;
+use toml::value::{Table, Value};
+
+use crate::config::RecorderConfig;
+use crate::container::Container;
+use crate::encoder::{Encoder, RateControl};
+use crate::error::{InvalidSetting, RecorderError};
+use crate::modes::PixelFormat;
+use crate::source::VideoSource;
+
+/// Prefix of the environment variables overriding settings.
+pub const ENV_PREFIX: &str = "GST_CAMERA_";
+
+/// Sections whose keys can be addressed as `GST_CAMERA_<SECTION>_<KEY>`.
+const SECTIONS: &[&str] = &[
+    "source", "video", "encoder", "output", "retention", "storage", "restart", "slate", "watchdog",
+    "corruption",
+];
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    pub name: Option<String>,
+    /// Shorthand for a V4L2 `source.device`.
+    pub device: Option<String>,
+    #[serde(default)]
+    pub source: SourceSettings,
+    #[serde(default)]
+    pub video: VideoSettings,
+    #[serde(default)]
+    pub encoder: EncoderSettings,
+    #[serde(default)]
+    pub output: OutputSettings,
+    #[serde(default)]
+    pub retention: RetentionSettings,
+    #[serde(default)]
+    pub storage: StorageSettings,
+    #[serde(default)]
+    pub restart: RestartSettings,
+    #[serde(default)]
+    pub slate: SlateSettings,
+    #[serde(default)]
+    pub watchdog: WatchdogSettings,
+    #[serde(default)]
+    pub corruption: CorruptionSettings,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    V4l2,
+    Test,
+    File,
+    Uri,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SourceSettings {
+    /// Defaults to v4l2.
+    pub kind: Option<SourceKind>,
+    pub device: Option<String>,
+    pub pattern: Option<String>,
+    pub path: Option<PathBuf>,
+    pub uri: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VideoSettings {
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub framerate: Option<i32>,
+    pub max_width: Option<i32>,
+    pub max_height: Option<i32>,
+    pub min_framerate: Option<i32>,
+    /// Only use modes of this pixel format, e.g. "mjpeg" or "yuy2".
+    pub format: Option<String>,
+    pub prefer_format: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EncoderSettings {
+    pub kind: Option<Encoder>,
+    /// kbit/s
+    pub bitrate: Option<u32>,
+    pub rate_control: Option<RateControl>,
+    pub quantizer: Option<u32>,
+    /// Preset name for x264/x265, CPU usage level for VP8/VP9/AV1.
+    #[serde(default, deserialize_with = "string_or_number")]
+    pub speed_preset: Option<String>,
+    pub tune: Option<String>,
+    pub key_int_max: Option<u32>,
+    #[serde(default, deserialize_with = "string_or_number")]
+    pub profile: Option<String>,
+    /// Record the camera's MJPEG as is, see [`RecorderConfig::passthrough`].
+    pub passthrough: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputSettings {
+    pub location: Option<String>,
+    /// seconds
+    pub segment_duration: Option<u64>,
+    /// Split at wall-clock multiples of the segment duration.
+    pub align_splits: Option<bool>,
+    /// Guessed from the location extension when unset.
+    pub container: Option<Container>,
+    /// milliseconds, fragmented MP4 only
+    pub fragment_duration: Option<u64>,
+    pub streamable: Option<bool>,
+    /// seconds to wait for the last segment to be finalized on stop
+    pub shutdown_timeout: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionSettings {
+    /// bytes over all segments
+    pub max_bytes: Option<u64>,
+    pub max_files: Option<usize>,
+    /// seconds
+    pub max_age: Option<u64>,
+}
+
+/// Free space thresholds in bytes, see [`crate::storage::StoragePolicy`].
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StorageSettings {
+    /// seconds
+    pub check_interval: Option<u64>,
+    pub purge_below: Option<u64>,
+    pub lower_bitrate_below: Option<u64>,
+    /// kbit/s
+    pub low_bitrate: Option<u32>,
+    pub pause_below: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RestartSettings {
+    pub enabled: Option<bool>,
+    /// seconds
+    pub initial_backoff: Option<u64>,
+    /// seconds
+    pub max_backoff: Option<u64>,
+    pub max_restarts: Option<u32>,
+    /// seconds
+    pub window: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SlateSettings {
+    pub enabled: Option<bool>,
+    /// milliseconds without buffers before the slate is shown
+    pub timeout: Option<u64>,
+    pub text: Option<String>,
+    pub pattern: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatchdogSettings {
+    pub enabled: Option<bool>,
+    /// milliseconds without buffers before the source counts as stalled
+    pub timeout: Option<u64>,
+    pub restart: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CorruptionSettings {
+    pub max_percent: Option<u32>,
+    /// seconds
+    pub window: Option<u64>,
+    /// seconds
+    pub report_interval: Option<u64>,
+}
+
+impl Settings {
+    /// Applies the settings on top of the recorder defaults and validates
+    /// the result.
+    pub fn into_config(self) -> Result<RecorderConfig, InvalidSetting> {
+        if self.output.location.is_none() {
+            return Err(InvalidSetting::new("output.location", "missing"));
+        }
+        let config = self.apply()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Like [`Settings::into_config`] for capturing without recording, the
+    /// output settings are neither required nor checked.
+    pub fn into_capture_config(self) -> Result<RecorderConfig, InvalidSetting> {
+        let config = self.apply()?;
+        config.validate_capture()?;
+        Ok(config)
+    }
+
+    /// Applies the settings on top of the recorder defaults.
+    fn apply(self) -> Result<RecorderConfig, InvalidSetting> {
+        let location = self.output.location.unwrap_or_default();
+        let missing = |key: &str| InvalidSetting::new(key, "missing");
+        let source = match self.source.kind.unwrap_or(SourceKind::V4l2) {
+            SourceKind::V4l2 => VideoSource::V4l2 {
+                device: self.source.device.or(self.device).ok_or_else(|| missing("source.device"))?,
+            },
+            SourceKind::Test => VideoSource::Test {
+                pattern: self.source.pattern.unwrap_or_else(|| String::from("smpte")),
+            },
+            SourceKind::File => VideoSource::File {
+                path: self.source.path.ok_or_else(|| missing("source.path"))?,
+            },
+            SourceKind::Uri => VideoSource::Uri {
+                uri: self.source.uri.ok_or_else(|| missing("source.uri"))?,
+            },
+        };
+        let mut config = RecorderConfig::builder(source, location).build();
+
+        if let Some(name) = self.name {
+            config.name = name;
+        }
+
+        let parse_format = |key: &str, format: Option<String>| match format {
+            Some(format) => format.parse::<PixelFormat>()
+                .map(Some)
+                .map_err(|e| InvalidSetting::new(key, e.to_string())),
+            None => Ok(None),
+        };
+        let video = self.video;
+        let mode = &mut config.mode;
+        mode.width = video.width.or(mode.width);
+        mode.height = video.height.or(mode.height);
+        mode.framerate = video.framerate.or(mode.framerate);
+        mode.max_width = video.max_width.or(mode.max_width);
+        mode.max_height = video.max_height.or(mode.max_height);
+        mode.min_framerate = video.min_framerate.or(mode.min_framerate);
+        if let Some(format) = parse_format("video.format", video.format)? {
+            mode.format = Some(format);
+        }
+        if let Some(format) = parse_format("video.prefer_format", video.prefer_format)? {
+            mode.prefer_format = Some(format);
+        }
+
+        let encoder = &mut config.encoder;
+        if let Some(kind) = self.encoder.kind {
+            encoder.kind = kind;
+        }
+        encoder.bitrate = self.encoder.bitrate.or(encoder.bitrate);
+        encoder.rate_control = self.encoder.rate_control.or(encoder.rate_control);
+        encoder.quantizer = self.encoder.quantizer.or(encoder.quantizer);
+        encoder.speed_preset = self.encoder.speed_preset.or_else(|| encoder.speed_preset.take());
+        encoder.tune = self.encoder.tune.or_else(|| encoder.tune.take());
+        if let Some(key_int_max) = self.encoder.key_int_max {
+            encoder.key_int_max = key_int_max;
+        }
+        encoder.profile = self.encoder.profile.or_else(|| encoder.profile.take());
+        if let Some(passthrough) = self.encoder.passthrough {
+            config.passthrough = passthrough;
+        }
+
+        if let Some(segment_duration) = self.output.segment_duration {
+            config.max_size_time = gst::ClockTime::from_seconds(segment_duration);
+        }
+        if let Some(align_splits) = self.output.align_splits {
+            config.align_splits = align_splits;
+        }
+        let muxer = &mut config.muxer;
+        muxer.container = self.output.container.or(muxer.container);
+        if let Some(fragment_duration) = self.output.fragment_duration {
+            muxer.fragment_duration = Some(gst::ClockTime::from_mseconds(fragment_duration));
+        }
+        muxer.streamable = self.output.streamable.or(muxer.streamable);
+        if let Some(shutdown_timeout) = self.output.shutdown_timeout {
+            config.shutdown_timeout = gst::ClockTime::from_seconds(shutdown_timeout);
+        }
+
+        let retention = &mut config.retention;
+        retention.max_bytes = self.retention.max_bytes.or(retention.max_bytes);
+        retention.max_files = self.retention.max_files.or(retention.max_files);
+        if let Some(max_age) = self.retention.max_age {
+            retention.max_age = Some(Duration::from_secs(max_age));
+        }
+
+        let storage = &mut config.storage;
+        if let Some(check_interval) = self.storage.check_interval {
+            storage.check_interval = Duration::from_secs(check_interval);
+        }
+        storage.purge_below = self.storage.purge_below.or(storage.purge_below);
+        storage.lower_bitrate_below = self.storage.lower_bitrate_below.or(storage.lower_bitrate_below);
+        storage.low_bitrate = self.storage.low_bitrate.or(storage.low_bitrate);
+        storage.pause_below = self.storage.pause_below.or(storage.pause_below);
+
+        let restart = &mut config.restart;
+        restart.enabled = self.restart.enabled.unwrap_or(restart.enabled);
+        if let Some(initial_backoff) = self.restart.initial_backoff {
+            restart.initial_backoff = Duration::from_secs(initial_backoff);
+        }
+        if let Some(max_backoff) = self.restart.max_backoff {
+            restart.max_backoff = Duration::from_secs(max_backoff);
+        }
+        restart.max_restarts = self.restart.max_restarts.unwrap_or(restart.max_restarts);
+        if let Some(window) = self.restart.window {
+            restart.window = Duration::from_secs(window);
+        }
+
+        let slate = &mut config.slate;
+        slate.enabled = self.slate.enabled.unwrap_or(slate.enabled);
+        if let Some(timeout) = self.slate.timeout {
+            slate.timeout = Duration::from_millis(timeout);
+        }
+        if let Some(text) = self.slate.text {
+            slate.text = text;
+        }
+        if let Some(pattern) = self.slate.pattern {
+            slate.pattern = pattern;
+        }
+
+        let watchdog = &mut config.watchdog;
+        watchdog.enabled = self.watchdog.enabled.unwrap_or(watchdog.enabled);
+        if let Some(timeout) = self.watchdog.timeout {
+            watchdog.timeout = Duration::from_millis(timeout);
+        }
+        watchdog.restart = self.watchdog.restart.unwrap_or(watchdog.restart);
+
+        let corruption = &mut config.corruption;
+        corruption.max_percent = self.corruption.max_percent.unwrap_or(corruption.max_percent);
+        if let Some(window) = self.corruption.window {
+            corruption.window = Duration::from_secs(window);
+        }
+        if let Some(report_interval) = self.corruption.report_interval {
+            corruption.report_interval = Duration::from_secs(report_interval);
+        }
+
+        Ok(config)
+    }
+}
+
+/// Collects settings layers, later layers overriding earlier ones.
+#[derive(Debug, Default)]
+pub struct SettingsLoader {
+    root: Table,
+}
+
+impl SettingsLoader {
+    pub fn new() -> SettingsLoader {
+        SettingsLoader::default()
+    }
+
+    /// Adds the `[defaults]` of the file at `path` followed by the section
+    /// of `camera`. Without a camera name the file must define at most one.
+    pub fn file<P: AsRef<Path>>(mut self, path: P, camera: Option<&str>) -> Result<Self, RecorderError> {
+        let path = path.as_ref().display().to_string();
+        let invalid = |reason: String| RecorderError::SettingsFile { path: path.clone(), reason };
+
+        let text = fs::read_to_string(&path).map_err(|e| invalid(e.to_string()))?;
+        let mut file: Table = toml::from_str(&text).map_err(|e| invalid(e.to_string()))?;
+
+        let defaults = take_table(&mut file, "defaults")?;
+        let mut cameras = take_table(&mut file, "cameras")?;
+        if let Some(key) = file.keys().next() {
+            return Err(RecorderError::from(InvalidSetting::new(
+                key.as_str(),
+                "unknown section, expected defaults or cameras",
+            )));
+        }
+
+        let name = match camera {
+            Some(name) => Some(name.to_string()),
+            None if cameras.len() > 1 => {
+                let names = cameras.keys().cloned().collect::<Vec<_>>();
+                return Err(RecorderError::AmbiguousCamera { path, cameras: names.join(", ") });
+            }
+            None => cameras.keys().next().cloned(),
+        };
+
+        merge(&mut self.root, defaults);
+
+        if let Some(name) = name {
+            let key = format!("cameras.{}", name);
+            let camera = match cameras.remove(&name) {
+                Some(Value::Table(camera)) => camera,
+                Some(_) => return Err(RecorderError::from(InvalidSetting::new(key, "expected a table"))),
+                None => return Err(RecorderError::UnknownCamera { path, camera: name }),
+            };
+            merge(&mut self.root, camera);
+            self.root.insert(String::from("name"), Value::String(name));
+        }
+
+        Ok(self)
+    }
+
+    /// Adds every `GST_CAMERA_*` variable of the process environment.
+    pub fn env(self) -> Self {
+        self.vars(env::vars())
+    }
+
+    pub fn vars<I: IntoIterator<Item = (String, String)>>(mut self, vars: I) -> Self {
+        for (name, raw) in vars {
+            if !name.starts_with(ENV_PREFIX) {
+                continue;
+            }
+
+            let name = name[ENV_PREFIX.len()..].to_lowercase();
+            let key = SECTIONS.iter()
+                .find(|section| name.starts_with(&format!("{}_", section)))
+                .map(|section| format!("{}.{}", section, &name[section.len() + 1..]))
+                .unwrap_or(name);
+
+            self = self.set(&key, parse_value(&raw));
+        }
+        self
+    }
+
+    /// Sets a single dotted key, e.g. `encoder.bitrate`.
+    pub fn set<V: Into<Value>>(mut self, key: &str, value: V) -> Self {
+        let mut parts = key.split('.').collect::<Vec<_>>();
+        let last = parts.pop().unwrap_or_default();
+
+        let mut table = &mut self.root;
+        for part in parts {
+            let entry = table.entry(part.to_string())
+                .or_insert_with(|| Value::Table(Table::new()));
+            if !entry.is_table() {
+                *entry = Value::Table(Table::new());
+            }
+            table = match entry {
+                Value::Table(t) => t,
+                _ => unreachable!(),
+            };
+        }
+        table.insert(last.to_string(), value.into());
+
+        self
+    }
+
+    /// Like [`SettingsLoader::set`] but parses `raw` as a TOML value,
+    /// falling back to a plain string.
+    pub fn set_str(self, key: &str, raw: &str) -> Self {
+        self.set(key, parse_value(raw))
+    }
+
+    pub fn settings(self) -> Result<Settings, InvalidSetting> {
+        serde_path_to_error::deserialize(Value::Table(self.root)).map_err(|e| {
+            let key = e.path().to_string();
+            InvalidSetting::new(key, e.into_inner().to_string())
+        })
+    }
+
+    pub fn load(self) -> Result<RecorderConfig, InvalidSetting> {
+        self.settings()?.into_config()
+    }
+
+    /// See [`Settings::into_capture_config`].
+    pub fn load_capture(self) -> Result<RecorderConfig, InvalidSetting> {
+        self.settings()?.into_capture_config()
+    }
+}
+
+fn take_table(file: &mut Table, key: &str) -> Result<Table, InvalidSetting> {
+    match file.remove(key) {
+        Some(Value::Table(table)) => Ok(table),
+        Some(_) => Err(InvalidSetting::new(key, "expected a table")),
+        None => Ok(Table::new()),
+    }
+}
+
+fn merge(dst: &mut Table, src: Table) {
+    for (key, value) in src {
+        match (dst.get_mut(&key), value) {
+            (Some(Value::Table(dst)), Value::Table(src)) => merge(dst, src),
+            (_, value) => {
+                dst.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Accepts `profile = 0` as well as `profile = "0"`, environment values
+/// that look like numbers being parsed as such.
+fn string_or_number<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrNumber {
+        String(String),
+        Number(i64),
+    }
+
+    Ok(Option::<StringOrNumber>::deserialize(deserializer)?.map(|value| match value {
+        StringOrNumber::String(s) => s,
+        StringOrNumber::Number(n) => n.to_string(),
+    }))
+}
+
+fn parse_value(raw: &str) -> Value {
+    toml::from_str::<Table>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testutil::{rejected, temp_dir};
+
+    const FILE: &str = r#"
+[defaults]
+device = "/dev/video0"
+
+[defaults.encoder]
+kind = "x264"
+bitrate = 4000
+speed_preset = "veryfast"
+
+[defaults.output]
+location = "/srv/default%05d.mkv"
+
+[cameras.front.encoder]
+bitrate = 2000
+
+[cameras.front.output]
+location = "/srv/front%05d.mkv"
+
+[cameras.back]
+device = "/dev/video1"
+"#;
+
+    fn file(name: &str, text: &str) -> PathBuf {
+        let path = temp_dir(name).join("cameras.toml");
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn layers_camera_over_defaults() {
+        let path = file("settings-layers", FILE);
+
+        let config = SettingsLoader::new().file(&path, Some("front")).unwrap().load().unwrap();
+        assert_eq!(config.name, "front");
+        assert_eq!(config.source, VideoSource::V4l2 { device: String::from("/dev/video0") });
+        assert_eq!(config.encoder.kind, Encoder::X264);
+        assert_eq!(config.encoder.bitrate, Some(2000));
+        assert_eq!(config.encoder.speed_preset.as_deref(), Some("veryfast"));
+        assert_eq!(config.location, "/srv/front%05d.mkv");
+
+        let config = SettingsLoader::new().file(&path, Some("back")).unwrap().load().unwrap();
+        assert_eq!(config.source, VideoSource::V4l2 { device: String::from("/dev/video1") });
+        assert_eq!(config.encoder.bitrate, Some(4000));
+        assert_eq!(config.location, "/srv/default%05d.mkv");
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn layers_env_and_flags_over_the_file() {
+        let path = file("settings-overrides", FILE);
+
+        let config = SettingsLoader::new()
+            .file(&path, Some("front"))
+            .unwrap()
+            .vars(vars(&[
+                ("GST_CAMERA_ENCODER_BITRATE", "3000"),
+                ("GST_CAMERA_ENCODER_KEY_INT_MAX", "30"),
+                ("GST_CAMERA_OUTPUT_LOCATION", "/env%05d.mkv"),
+                ("GST_CAMERA_DEVICE", "/dev/video2"),
+                ("HOME", "/root"),
+            ]))
+            .set("encoder.bitrate", 1000)
+            .set_str("output.segment_duration", "30")
+            .load()
+            .unwrap();
+        assert_eq!(config.encoder.bitrate, Some(1000));
+        assert_eq!(config.encoder.key_int_max, 30);
+        assert_eq!(config.location, "/env%05d.mkv");
+        assert_eq!(config.source, VideoSource::V4l2 { device: String::from("/dev/video2") });
+        assert_eq!(config.max_size_time, gst::ClockTime::from_seconds(30));
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn requires_a_camera_among_several() {
+        let path = file("settings-cameras", FILE);
+
+        match SettingsLoader::new().file(&path, None) {
+            Err(RecorderError::AmbiguousCamera { cameras, .. }) => assert_eq!(cameras, "back, front"),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+        match SettingsLoader::new().file(&path, Some("side")) {
+            Err(RecorderError::UnknownCamera { camera, .. }) => assert_eq!(camera, "side"),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn names_the_unknown_section() {
+        let path = file("settings-section", "[camera.front]\ndevice = \"/dev/video0\"\n");
+
+        match SettingsLoader::new().file(&path, None) {
+            Err(RecorderError::Config(err)) => assert_eq!(err.key, "camera"),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn names_the_offending_key() {
+        let loader = || SettingsLoader::new().set("device", "/dev/video0").set("output.location", "video%05d.mkv");
+
+        assert_eq!(rejected(loader().set_str("encoder.bitrate", "fast").load()).key, "encoder.bitrate");
+        assert_eq!(rejected(loader().set("encoder.kind", "h263").load()).key, "encoder.kind");
+        assert_eq!(rejected(loader().set("retention.max_files", 0).load()).key, "retention.max_files");
+        assert_eq!(rejected(loader().set("video.width", 0).load()).key, "video.width");
+        assert_eq!(rejected(SettingsLoader::new().set("device", "/dev/video0").load()).key, "output.location");
+        assert_eq!(rejected(SettingsLoader::new().set("output.location", "video%05d.mkv").load()).key, "source.device");
+        let test_source = || loader().set("source.kind", "test");
+        assert!(test_source().set("source.pattern", "ball").load().is_ok());
+        assert_eq!(rejected(test_source().set("source.pattern", "smtpe").load()).key, "source.pattern");
+        assert_eq!(rejected(loader().set("slate.pattern", "noise").load()).key, "slate.pattern");
+
+        let err = rejected(loader().set("encoder.bitrat", 1000).load());
+        assert!(err.reason.contains("bitrat"), "{}", err);
+    }
+}
@@ -0,0 +1,197 @@
+use gstreamer as gst;
+use gst::prelude::*;
+
+use std::time::Duration;
+
+use crate::error::{InvalidSetting, RecorderError};
+use crate::pipeline::make_element;
+use crate::source::{self, source_pad_error};
+use crate::watchdog::{self, BufferTracker};
+
+/// Size and framerate of the slate until the camera caps are known.
+const SLATE_WIDTH: i32 = 1280;
+const SLATE_HEIGHT: i32 = 720;
+const SLATE_FRAMERATE: i32 = 30;
+
+const CONVERT: &str = "videoconvert";
+const FILTER: &str = "capsfilter";
+const SOURCE: &str = "videotestsrc";
+const TEXT: &str = "textoverlay";
+const CLOCK: &str = "clockoverlay";
+const SELECTOR: &str = "input-selector";
+
+/// Shows a generated slate instead of the camera while it sends nothing,
+/// so recordings stay continuous.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlateConfig {
+    pub enabled: bool,
+    /// How long the camera may send nothing before the slate is shown.
+    pub timeout: Duration,
+    /// Shown over the test pattern, above the current time.
+    pub text: String,
+    /// videotestsrc pattern nick behind the text.
+    pub pattern: String,
+}
+
+impl Default for SlateConfig {
+    fn default() -> Self {
+        SlateConfig {
+            enabled: false,
+            timeout: Duration::from_secs(2),
+            text: String::from("SIGNAL LOST"),
+            pattern: String::from("smpte"),
+        }
+    }
+}
+
+impl SlateConfig {
+    pub fn validate(&self) -> Result<(), InvalidSetting> {
+        if self.timeout.as_millis() == 0 {
+            return Err(InvalidSetting::new("slate.timeout", "must be greater than 0"));
+        }
+        source::validate_test_pattern("slate.pattern", &self.pattern)
+    }
+
+    /// How often the camera is checked for buffers.
+    pub fn check_interval(&self) -> Duration {
+        watchdog::check_interval(self.timeout)
+    }
+
+    /// Factories [`SlateConfig::build_fallback`] uses.
+    pub(crate) fn factories() -> &'static [&'static str] {
+        &[CONVERT, FILTER, SOURCE, TEXT, CLOCK, SELECTOR]
+    }
+
+    /// Wraps `source`, a bin with a raw video `src` pad, into a bin
+    /// switching between it and the slate.
+    ///
+    /// Both are converted to I420 and the slate follows the camera size and
+    /// framerate, so nothing downstream renegotiates on switching.
+    pub fn build_fallback(&self, source: gst::Bin) -> Result<gst::Bin, RecorderError> {
+        let bin = gst::Bin::new("fallback");
+
+        // camera branch
+        let camera_convert = make_element(CONVERT, "camera_convert")?;
+        let camera_filter = make_element(FILTER, "camera_filter")?;
+        camera_filter.set_property("caps", &raw_caps().build())?;
+
+        // slate branch
+        let slate_src = make_element(SOURCE, "slate_src")?;
+        slate_src.set_property("is-live", &true)?;
+        slate_src.set_property_from_str("pattern", &self.pattern);
+        let slate_filter = make_element(FILTER, "slate_filter")?;
+        let slate_caps = raw_caps()
+            .field("width", &SLATE_WIDTH)
+            .field("height", &SLATE_HEIGHT)
+            .field("framerate", &gst::Fraction::new(SLATE_FRAMERATE, 1))
+            .build();
+        slate_filter.set_property("caps", &slate_caps)?;
+        let slate_text = make_element(TEXT, "slate_text")?;
+        slate_text.set_property("text", &self.text)?;
+        slate_text.set_property_from_str("valignment", "center");
+        slate_text.set_property_from_str("halignment", "center");
+        slate_text.set_property("font-desc", &"Sans Bold 48")?;
+        let slate_clock = make_element(CLOCK, "slate_clock")?;
+        slate_clock.set_property("time-format", &"%Y-%m-%d %H:%M:%S")?;
+        slate_clock.set_property_from_str("valignment", "bottom");
+        slate_clock.set_property_from_str("halignment", "center");
+
+        // selector, dropping whatever the inactive branch sends
+        let selector = make_element(SELECTOR, "fallback_selector")?;
+        selector.set_property("sync-streams", &false)?;
+
+        let source = source.upcast::<gst::Element>();
+        bin.add_many(&[
+            &source,
+            &camera_convert,
+            &camera_filter,
+            &slate_src,
+            &slate_filter,
+            &slate_text,
+            &slate_clock,
+            &selector,
+        ])?;
+        gst::Element::link_many(&[&source, &camera_convert, &camera_filter, &selector])?;
+        gst::Element::link_many(&[&slate_src, &slate_filter, &slate_text, &slate_clock, &selector])?;
+
+        // the first linked request pad, the camera, is the active one
+        let camera_pad = camera_filter.get_static_pad("src").ok_or_else(source_pad_error)?;
+        camera_pad.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+            if let Some(gst::PadProbeData::Event(ref event)) = info.data {
+                if let gst::EventView::Caps(caps) = event.view() {
+                    if let Some(s) = caps.get_caps().get_structure(0) {
+                        let mut slate_structure = gst::Structure::new_empty("video/x-raw");
+                        slate_structure.set("format", &"I420");
+                        for field in &["width", "height", "framerate", "pixel-aspect-ratio"] {
+                            if let Some(value) = s.get_value(field) {
+                                slate_structure.set_value(field, value.clone());
+                            }
+                        }
+                        let mut slate_caps = gst::Caps::new_empty();
+                        if let Some(slate_caps) = slate_caps.get_mut() {
+                            slate_caps.append_structure(slate_structure);
+                        }
+                        let _ = slate_filter.set_property("caps", &slate_caps);
+                    }
+                }
+            }
+            gst::PadProbeReturn::Ok
+        });
+
+        let src_pad = selector.get_static_pad("src").ok_or_else(source_pad_error)?;
+        let ghost_pad = gst::GhostPad::new("src", &src_pad).ok_or_else(source_pad_error)?;
+        bin.add_pad(&ghost_pad)?;
+
+        Ok(bin)
+    }
+}
+
+fn raw_caps() -> gst::caps::Builder<'static> {
+    gst::Caps::builder("video/x-raw").field("format", &"I420")
+}
+
+/// Switches the fallback selector of a running pipeline to the slate when
+/// the camera stops sending, and back once it sends again.
+pub struct SlateSwitch {
+    selector: gst::Element,
+    camera_pad: gst::Pad,
+    slate_pad: gst::Pad,
+    camera: BufferTracker,
+    timeout: Duration,
+    showing: bool,
+}
+
+impl SlateSwitch {
+    /// Starts tracking the camera buffers of `pipeline`, `None` if it has
+    /// no fallback.
+    pub fn attach(pipeline: &gst::Pipeline, timeout: Duration) -> Option<SlateSwitch> {
+        let selector = pipeline.get_by_name("fallback_selector")?;
+        let camera_pad = selector.get_static_pad("sink_0")?;
+        let slate_pad = selector.get_static_pad("sink_1")?;
+
+        Some(SlateSwitch {
+            camera: BufferTracker::attach(&camera_pad),
+            selector,
+            camera_pad,
+            slate_pad,
+            timeout,
+            showing: false,
+        })
+    }
+
+    /// Switches if needed, returns `Some(true)` when the slate got shown
+    /// and `Some(false)` when the camera is back.
+    pub fn check(&mut self) -> Option<bool> {
+        let stalled = self.camera.elapsed() > self.timeout;
+        if stalled == self.showing {
+            return None;
+        }
+
+        let pad = if stalled { &self.slate_pad } else { &self.camera_pad };
+        if self.selector.set_property("active-pad", pad).is_err() {
+            return None;
+        }
+        self.showing = stalled;
+        Some(stalled)
+    }
+}
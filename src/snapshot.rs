@@ -0,0 +1,116 @@
+use gstreamer as gst;
+use gstreamer_video as gst_video;
+use gst::prelude::*;
+
+use std::path::Path;
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
+
+use crate::config::RecorderConfig;
+use crate::devices;
+use crate::error::RecorderError;
+use crate::modes::PixelFormat;
+use crate::pipeline::{make_element, run_to_eos};
+use crate::source::VideoSource;
+
+/// Grabs a single JPEG frame from the configured source into `output`.
+///
+/// MJPEG frames are written as they come from the camera, anything else
+/// gets encoded with jpegenc.
+pub fn snapshot(config: &RecorderConfig, output: &Path) -> Result<(), RecorderError> {
+    gst::init()?;
+
+    let pipeline = gst::Pipeline::new("camera-snapshot");
+
+    let mut elements = match &config.source {
+        VideoSource::V4l2 { device } => {
+            // video source
+            let device = devices::resolve(device)?;
+            let v4l2src = make_element("v4l2src", "v4l2src")?;
+            v4l2src.set_property("device", &device)?;
+            v4l2src.set_property("num-buffers", &1i32)?;
+
+            // video filter
+            let mode = devices::select_mode(&device, &config.mode)?;
+            let video_filter = make_element("capsfilter", None)?;
+            video_filter.set_property("caps", &mode.caps())?;
+
+            let mut elements = vec![v4l2src, video_filter];
+            if let PixelFormat::Raw(_) = mode.format {
+                elements.push(make_element("videoconvert", "videoconvert")?);
+                elements.push(make_element("jpegenc", "jpegenc")?);
+            }
+            elements
+        }
+        _ => {
+            // video source, producing raw video
+            let source = config.source.build(&config.mode)?.upcast::<gst::Element>();
+
+            // jpeg encoder, sending EOS after the first frame
+            let jpegenc = make_element("jpegenc", "jpegenc")?;
+            jpegenc.set_property("snapshot", &true)?;
+
+            vec![source, make_element("videoconvert", "videoconvert")?, jpegenc]
+        }
+    };
+
+    // sink
+    let filesink = make_element("filesink", "filesink")?;
+    filesink.set_property("location", &output.to_string_lossy().as_ref())?;
+
+    elements.push(filesink);
+
+    let elements = elements.iter().collect::<Vec<_>>();
+    pipeline.add_many(&elements)?;
+    gst::Element::link_many(&elements)?;
+
+    run_to_eos(&pipeline)
+}
+
+/// Grabs the next frame out of the source of a running recording
+/// `pipeline` as JPEG, waiting up to `timeout` for it.
+///
+/// The camera is busy recording, so unlike [`snapshot`] this taps the
+/// pipeline rather than opening the device.
+pub fn grab(pipeline: &gst::Pipeline, timeout: Duration) -> Result<Vec<u8>, RecorderError> {
+    let pad = pipeline.get_by_name("source")
+        .and_then(|source| source.get_static_pad("src"))
+        .ok_or_else(|| RecorderError::pipeline("the pipeline has no source to grab a frame from"))?;
+
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(Some(tx));
+    let probe = pad.add_probe(gst::PadProbeType::BUFFER, move |pad, info| {
+        if let Some(gst::PadProbeData::Buffer(buffer)) = &info.data {
+            if let Some(tx) = tx.lock().unwrap().take() {
+                let _ = tx.send((buffer.clone(), pad.get_current_caps()));
+            }
+        }
+        gst::PadProbeReturn::Remove
+    });
+
+    let (buffer, caps) = match rx.recv_timeout(timeout) {
+        Ok(frame) => frame,
+        Err(_) => {
+            if let Some(probe) = probe {
+                pad.remove_probe(probe);
+            }
+            return Err(RecorderError::pipeline(format!("no frame within {:?}", timeout)));
+        }
+    };
+    let caps = caps.ok_or_else(|| RecorderError::pipeline("the source has no caps"))?;
+
+    // the camera's MJPEG is written as is
+    let is_jpeg = caps.get_structure(0).map(|s| s.get_name() == "image/jpeg").unwrap_or(false);
+    let jpeg = if is_jpeg {
+        buffer
+    } else {
+        let sample = gst::Sample::new::<gst::ClockTime>(Some(&buffer), Some(&caps), None, None);
+        let jpeg_caps = gst::Caps::new_simple("image/jpeg", &[]);
+        gst_video::convert_sample(&sample, &jpeg_caps, gst::ClockTime::from_mseconds(timeout.as_millis() as u64))?
+            .get_buffer()
+            .ok_or_else(|| RecorderError::pipeline("the converted frame is empty"))?
+    };
+
+    let map = jpeg.map_readable().ok_or_else(|| RecorderError::pipeline("cannot read the frame"))?;
+    Ok(map.as_slice().to_vec())
+}
@@ -0,0 +1,304 @@
+use gstreamer as gst;
+use gst::prelude::*;
+
+use std::convert::Infallible;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::devices;
+use crate::error::{InvalidSetting, RecorderError};
+use crate::modes::{ModeConstraints, PixelFormat};
+use crate::pipeline::{decoder_factory, make_decoder, make_element};
+
+const FILTER: &str = "capsfilter";
+const CONVERT: &str = "videoconvert";
+/// Sink for the streams of files and URIs other than video.
+const DISCARD: &str = "fakesink";
+
+/// Resolution and framerate of the test source when the mode constraints
+/// don't pin them.
+const TEST_WIDTH: i32 = 1280;
+const TEST_HEIGHT: i32 = 720;
+const TEST_FRAMERATE: i32 = 30;
+
+/// Nicks of the `videotestsrc` patterns, setting any other name is
+/// silently ignored.
+const TEST_PATTERNS: &[&str] = &[
+    "smpte", "snow", "black", "white", "red", "green", "blue", "checkers-1", "checkers-2",
+    "checkers-4", "checkers-8", "circular", "blink", "smpte75", "zone-plate", "gamut",
+    "chroma-zone-plate", "solid-color", "ball", "smpte100", "bar", "pinwheel", "spokes",
+    "gradient", "colors", "smpte-rp-219",
+];
+
+/// Where the recorded video comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VideoSource {
+    /// A V4L2 capture device such as `/dev/video0`, a stable symlink to one
+    /// like `/dev/v4l/by-id/...`, or `serial:<serial>`.
+    V4l2 { device: String },
+    /// A live `videotestsrc`, `pattern` being one of its pattern nicks
+    /// (smpte, ball, snow, ...).
+    Test { pattern: String },
+    /// A local media file.
+    File { path: PathBuf },
+    /// Anything `uridecodebin` can play, e.g. `rtsp://` streams.
+    Uri { uri: String },
+}
+
+impl VideoSource {
+    /// Builds a bin with a single `src` pad producing raw video.
+    ///
+    /// The camera mode is selected here for V4L2 devices, the test source
+    /// honours the requested size and framerate, files and URIs produce
+    /// whatever they contain.
+    pub fn build(&self, mode: &ModeConstraints) -> Result<gst::Bin, RecorderError> {
+        let bin = gst::Bin::new("source");
+
+        let src_pad = match self {
+            VideoSource::V4l2 { device } => {
+                let device = devices::resolve(device)?;
+                let v4l2src = make_element(self.factory(), "v4l2src")?;
+                v4l2src.set_property("device", &device)?;
+
+                let selected = devices::select_mode(&device, mode)?;
+                let video_filter = make_element(FILTER, "video_filter")?;
+                video_filter.set_property("caps", &selected.caps())?;
+
+                let decoder = make_decoder(&selected.format)?;
+
+                bin.add_many(&[&v4l2src, &video_filter, &decoder])?;
+                gst::Element::link_many(&[&v4l2src, &video_filter, &decoder])?;
+
+                decoder.get_static_pad("src")
+            }
+            VideoSource::Test { pattern } => {
+                let videotestsrc = make_element(self.factory(), "videotestsrc")?;
+                videotestsrc.set_property("is-live", &true)?;
+                videotestsrc.set_property_from_str("pattern", pattern);
+
+                let width = mode.width.or(mode.max_width).unwrap_or(TEST_WIDTH);
+                let height = mode.height.or(mode.max_height).unwrap_or(TEST_HEIGHT);
+                let framerate = mode.framerate.or(mode.min_framerate).unwrap_or(TEST_FRAMERATE);
+                let video_filter = make_element(FILTER, "video_filter")?;
+                let video_caps = gst::Caps::builder("video/x-raw")
+                    .field("width", &width)
+                    .field("height", &height)
+                    .field("framerate", &gst::Fraction::new(framerate, 1))
+                    .build();
+                video_filter.set_property("caps", &video_caps)?;
+
+                bin.add_many(&[&videotestsrc, &video_filter])?;
+                videotestsrc.link(&video_filter)?;
+
+                video_filter.get_static_pad("src")
+            }
+            VideoSource::File { .. } | VideoSource::Uri { .. } => {
+                let uridecodebin = make_element(self.factory(), "uridecodebin")?;
+                uridecodebin.set_property("uri", &self.uri()?)?;
+
+                let videoconvert = make_element(CONVERT, "videoconvert")?;
+
+                bin.add_many(&[&uridecodebin, &videoconvert])?;
+                link_decoded_video(&uridecodebin, &videoconvert)?;
+
+                videoconvert.get_static_pad("src")
+            }
+        };
+
+        add_ghost_pad(&bin, src_pad)?;
+
+        Ok(bin)
+    }
+
+    /// Builds a bin with a single `src` pad producing the camera's own
+    /// `image/jpeg` frames, for recording without decoding.
+    ///
+    /// Only V4L2 devices are supported, and only their MJPEG modes are
+    /// considered.
+    pub fn build_passthrough(&self, mode: &ModeConstraints) -> Result<gst::Bin, RecorderError> {
+        let device = match self {
+            VideoSource::V4l2 { device } => device,
+            _ => return Err(RecorderError::PassthroughUnsupported(self.to_string())),
+        };
+
+        let bin = gst::Bin::new("source");
+
+        let device = devices::resolve(device)?;
+        let v4l2src = make_element(self.factory(), "v4l2src")?;
+        v4l2src.set_property("device", &device)?;
+
+        let mjpeg_mode = ModeConstraints {
+            format: Some(PixelFormat::Mjpeg),
+            ..mode.clone()
+        };
+        let selected = devices::select_mode(&device, &mjpeg_mode)?;
+        let video_filter = make_element(FILTER, "video_filter")?;
+        video_filter.set_property("caps", &selected.caps())?;
+
+        bin.add_many(&[&v4l2src, &video_filter])?;
+        v4l2src.link(&video_filter)?;
+
+        add_ghost_pad(&bin, video_filter.get_static_pad("src"))?;
+
+        Ok(bin)
+    }
+
+    /// Factories this source is built from, see [`crate::pipeline::factories`].
+    pub(crate) fn factories(&self, passthrough: bool, format: Option<&PixelFormat>) -> Vec<Vec<&'static str>> {
+        let mut factories = vec![vec![self.factory()]];
+        match self {
+            VideoSource::V4l2 { .. } => {
+                factories.push(vec![FILTER]);
+                if !passthrough {
+                    match format {
+                        Some(format) => factories.push(vec![decoder_factory(format)]),
+                        None => {
+                            factories.push(vec![decoder_factory(&PixelFormat::Mjpeg)]);
+                            factories.push(vec![CONVERT]);
+                        }
+                    }
+                }
+            }
+            VideoSource::Test { .. } => factories.push(vec![FILTER]),
+            VideoSource::File { .. } | VideoSource::Uri { .. } => {
+                factories.push(vec![CONVERT]);
+                factories.push(vec![DISCARD]);
+            }
+        }
+        factories
+    }
+
+    /// The element producing the video.
+    fn factory(&self) -> &'static str {
+        match self {
+            VideoSource::V4l2 { .. } => "v4l2src",
+            VideoSource::Test { .. } => "videotestsrc",
+            VideoSource::File { .. } | VideoSource::Uri { .. } => "uridecodebin",
+        }
+    }
+
+    /// The camera of V4L2 sources, which may come and go.
+    pub fn device(&self) -> Option<&str> {
+        match self {
+            VideoSource::V4l2 { device } => Some(device),
+            _ => None,
+        }
+    }
+
+    /// URI handed to `uridecodebin` for file and URI sources.
+    fn uri(&self) -> Result<String, RecorderError> {
+        match self {
+            VideoSource::File { path } => {
+                let path = fs::canonicalize(path)
+                    .map_err(|e| InvalidSetting::new("source.path", format!("{}: {}", path.display(), e)))?;
+                Ok(glib::filename_to_uri(&path, None)?.to_string())
+            }
+            VideoSource::Uri { uri } => Ok(uri.clone()),
+            _ => unreachable!("only file and URI sources have an URI"),
+        }
+    }
+}
+
+pub(crate) fn source_pad_error() -> RecorderError {
+    RecorderError::pipeline("Cannot create source bin pad")
+}
+
+fn add_ghost_pad(bin: &gst::Bin, src_pad: Option<gst::Pad>) -> Result<(), RecorderError> {
+    let src_pad = src_pad.ok_or_else(source_pad_error)?;
+    let ghost_pad = gst::GhostPad::new("src", &src_pad).ok_or_else(source_pad_error)?;
+    bin.add_pad(&ghost_pad)?;
+    Ok(())
+}
+
+/// Links the first decoded video stream of `decodebin` to `sink`, any other
+/// stream is discarded in a fakesink so it doesn't stall the pipeline.
+fn link_decoded_video(decodebin: &gst::Element, sink: &gst::Element) -> Result<(), RecorderError> {
+    let sink_pad = sink.get_static_pad("sink").ok_or_else(source_pad_error)?;
+    // fail early, the signal handler can't report a missing element
+    gst::ElementFactory::find(DISCARD).ok_or(RecorderError::MissingElement(DISCARD))?;
+
+    decodebin.connect_pad_added(move |decodebin, src_pad| {
+        let is_video = src_pad.get_current_caps()
+            .and_then(|caps| caps.get_structure(0).map(|s| s.get_name().starts_with("video/")))
+            .unwrap_or(false);
+
+        if is_video && !sink_pad.is_linked() {
+            let _ = src_pad.link(&sink_pad);
+            return;
+        }
+
+        let bin = match decodebin.get_parent().and_then(|p| p.downcast::<gst::Bin>().ok()) {
+            Some(bin) => bin,
+            None => return,
+        };
+        if let Some(fakesink) = gst::ElementFactory::make(DISCARD, None) {
+            let _ = fakesink.set_property("sync", &false);
+            if bin.add(&fakesink).is_ok() {
+                if let Some(fake_pad) = fakesink.get_static_pad("sink") {
+                    let _ = src_pad.link(&fake_pad);
+                }
+                let _ = fakesink.sync_state_with_parent();
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Checks that `pattern`, set through the setting `key`, is a
+/// `videotestsrc` pattern nick.
+pub(crate) fn validate_test_pattern(key: &str, pattern: &str) -> Result<(), InvalidSetting> {
+    if TEST_PATTERNS.contains(&pattern) {
+        return Ok(());
+    }
+    Err(InvalidSetting::new(
+        key,
+        format!("unknown pattern '{}', use one of {}", pattern, TEST_PATTERNS.join(", ")),
+    ))
+}
+
+impl From<&str> for VideoSource {
+    fn from(device: &str) -> Self {
+        VideoSource::V4l2 { device: device.into() }
+    }
+}
+
+impl From<String> for VideoSource {
+    fn from(device: String) -> Self {
+        VideoSource::V4l2 { device }
+    }
+}
+
+/// Parses the short form used on the command line: `/dev/...` devices,
+/// `serial:<serial>` cameras, `test` or `test:<pattern>`, `<scheme>://` URIs, anything else being a
+/// file path.
+impl FromStr for VideoSource {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("/dev/") || s.starts_with("serial:") {
+            Ok(VideoSource::V4l2 { device: s.into() })
+        } else if s == "test" {
+            Ok(VideoSource::Test { pattern: String::from("smpte") })
+        } else if let Some(pattern) = s.strip_prefix("test:") {
+            Ok(VideoSource::Test { pattern: pattern.into() })
+        } else if s.contains("://") {
+            Ok(VideoSource::Uri { uri: s.into() })
+        } else {
+            Ok(VideoSource::File { path: s.into() })
+        }
+    }
+}
+
+impl fmt::Display for VideoSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VideoSource::V4l2 { device } => f.write_str(device),
+            VideoSource::Test { pattern } => write!(f, "test:{}", pattern),
+            VideoSource::File { path } => write!(f, "{}", path.display()),
+            VideoSource::Uri { uri } => f.write_str(uri),
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Splitting segments on demand and at wall-clock boundaries.
+//!
+//! splitmuxsink only starts a new segment on a keyframe, so every split
+//! also asks the encoder for one to have it happen right away.
+
+use gstreamer as gst;
+use gstreamer_video as gst_video;
+use gst::prelude::*;
+
+use std::time::Duration;
+
+use crate::error::RecorderError;
+
+/// How often [`SplitClock::check`] is meant to be called.
+pub(crate) const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Closes the current segment of the recording `pipeline` on the next
+/// keyframe, requested from the encoder.
+pub(crate) fn split_now(pipeline: &gst::Pipeline) -> Result<(), RecorderError> {
+    let splitmuxsink = pipeline.get_by_name("splitmuxsink")
+        .ok_or(RecorderError::MissingElement("splitmuxsink"))?;
+    splitmuxsink.emit("split-now", &[])?;
+
+    // passthrough MJPEG is all keyframes, there is no encoder to ask
+    if let Some(pad) = pipeline.get_by_name("encoder").and_then(|encoder| encoder.get_static_pad("src")) {
+        pad.send_event(gst_video::new_upstream_force_key_unit_event().all_headers(true).build());
+    }
+
+    Ok(())
+}
+
+/// Tells when the local time crosses a multiple of the segment duration
+/// counted from midnight, e.g. every full minute for 60 s.
+pub(crate) struct SplitClock {
+    /// seconds
+    interval: i64,
+    /// Number of the interval the local time was in when last checked.
+    current: i64,
+}
+
+impl SplitClock {
+    pub(crate) fn new(interval: Duration) -> SplitClock {
+        SplitClock::starting_at(interval, local_seconds())
+    }
+
+    /// Clock started at `now`, in seconds since the epoch on the local
+    /// clock.
+    fn starting_at(interval: Duration, now: i64) -> SplitClock {
+        let interval = (interval.as_secs() as i64).max(1);
+        SplitClock {
+            interval,
+            current: now.div_euclid(interval),
+        }
+    }
+
+    /// Whether a boundary was crossed since the last check.
+    ///
+    /// A change of the UTC offset moving the local time into another
+    /// interval counts as crossing one, so segments get realigned right
+    /// away.
+    pub(crate) fn crossed(&mut self) -> bool {
+        self.crossed_at(local_seconds())
+    }
+
+    fn crossed_at(&mut self, now: i64) -> bool {
+        let current = now.div_euclid(self.interval);
+        let crossed = current != self.current;
+        self.current = current;
+        crossed
+    }
+}
+
+/// Seconds since the epoch on the local clock, so that days start at
+/// local midnight.
+fn local_seconds() -> i64 {
+    let now = glib::DateTime::new_now_local();
+    now.to_unix() + now.get_utc_offset() / 1_000_000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Local seconds since the epoch at `hours:minutes:seconds` on the
+    /// 1000th day.
+    fn at(hours: i64, minutes: i64, seconds: i64) -> i64 {
+        1000 * 86_400 + hours * 3600 + minutes * 60 + seconds
+    }
+
+    /// Checks every second from `from` on, returns when boundaries were
+    /// crossed.
+    fn crossings(clock: &mut SplitClock, from: i64, seconds: i64) -> Vec<i64> {
+        (from..from + seconds).filter(|now| clock.crossed_at(*now)).collect()
+    }
+
+    #[test]
+    fn splits_at_multiples_from_midnight() {
+        // the first segment only lasts until the next full minute
+        let mut clock = SplitClock::starting_at(Duration::from_secs(60), at(10, 0, 45));
+        assert_eq!(
+            crossings(&mut clock, at(10, 0, 46), 150),
+            vec![at(10, 1, 0), at(10, 2, 0), at(10, 3, 0)],
+        );
+
+        // 15 minutes are counted from midnight, not from the start
+        let mut clock = SplitClock::starting_at(Duration::from_secs(900), at(23, 50, 0));
+        assert_eq!(crossings(&mut clock, at(23, 50, 1), 1800), vec![at(0, 0, 0) + 86_400, at(0, 15, 0) + 86_400]);
+    }
+
+    #[test]
+    fn realigns_when_the_offset_changes() {
+        let mut clock = SplitClock::starting_at(Duration::from_secs(900), at(2, 50, 0));
+        assert!(!clock.crossed_at(at(2, 59, 59)));
+        // back to standard time, in the middle of a quarter hour
+        assert!(clock.crossed_at(at(2, 0, 0)));
+        assert_eq!(crossings(&mut clock, at(2, 0, 1), 900), vec![at(2, 15, 0)]);
+    }
+
+    #[test]
+    fn splits_at_least_every_second() {
+        let mut clock = SplitClock::starting_at(Duration::from_millis(500), at(0, 0, 0));
+        assert_eq!(crossings(&mut clock, at(0, 0, 1), 3), vec![at(0, 0, 1), at(0, 0, 2), at(0, 0, 3)]);
+    }
+}
@@ -0,0 +1,136 @@
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::error::{InvalidSetting, RecorderError};
+use crate::retention::{self, Segment};
+
+/// What the recorder does when free space runs low.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LowSpaceAction {
+    /// Delete the oldest segments.
+    Purge,
+    /// Encode at the low bitrate.
+    LowerBitrate,
+    /// Stop writing until there is space again.
+    Pause,
+}
+
+impl fmt::Display for LowSpaceAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LowSpaceAction::Purge => "purging oldest segments",
+            LowSpaceAction::LowerBitrate => "lowering bitrate",
+            LowSpaceAction::Pause => "pausing recording",
+        })
+    }
+}
+
+/// Free space thresholds of the output filesystem, in bytes, and the action
+/// taken below each of them. Unset thresholds don't apply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoragePolicy {
+    pub check_interval: Duration,
+    /// Delete the oldest segments until this much space is free.
+    pub purge_below: Option<u64>,
+    /// Encode at `low_bitrate` while less space is free.
+    pub lower_bitrate_below: Option<u64>,
+    /// kbit/s
+    pub low_bitrate: Option<u32>,
+    /// Stop recording while less space is free.
+    pub pause_below: Option<u64>,
+}
+
+impl Default for StoragePolicy {
+    fn default() -> Self {
+        StoragePolicy {
+            check_interval: Duration::from_secs(5),
+            purge_below: None,
+            lower_bitrate_below: None,
+            low_bitrate: None,
+            pause_below: None,
+        }
+    }
+}
+
+impl StoragePolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.purge_below.is_some() || self.lower_bitrate_below.is_some() || self.pause_below.is_some()
+    }
+
+    /// Lowest threshold, below which recording can't go on unless paused.
+    pub fn min_free(&self) -> Option<u64> {
+        [self.purge_below, self.lower_bitrate_below, self.pause_below]
+            .iter()
+            .filter_map(|threshold| *threshold)
+            .min()
+    }
+
+    /// `can_lower_bitrate` tells whether the encoder has a bitrate at all.
+    pub fn validate(&self, can_lower_bitrate: bool) -> Result<(), InvalidSetting> {
+        if self.check_interval.as_millis() == 0 {
+            return Err(InvalidSetting::new("storage.check_interval", "must be greater than 0"));
+        }
+        if self.lower_bitrate_below.is_some() {
+            if !can_lower_bitrate {
+                return Err(InvalidSetting::new(
+                    "storage.lower_bitrate_below",
+                    "the encoder has no bitrate to lower",
+                ));
+            }
+            match self.low_bitrate {
+                None => return Err(InvalidSetting::new("storage.low_bitrate", "missing")),
+                Some(0) => return Err(InvalidSetting::new("storage.low_bitrate", "must be greater than 0")),
+                Some(_) => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `path`,
+/// or its closest existing ancestor.
+pub fn free_space(path: &Path) -> Result<u64, RecorderError> {
+    let existing = path.ancestors()
+        .find(|dir| dir.as_os_str().is_empty() || dir.exists())
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let c_path = CString::new(existing.as_os_str().as_bytes())
+        .map_err(|e| InvalidSetting::new("output.location", e.to_string()))?;
+
+    unsafe {
+        let mut stat: libc::statvfs = mem::zeroed();
+        if libc::statvfs(c_path.as_ptr(), &mut stat) != 0 {
+            return Err(RecorderError::Storage(io::Error::last_os_error()));
+        }
+        #[allow(clippy::unnecessary_cast)]
+        Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+}
+
+/// Deletes the oldest segments written to `location` until `target` bytes
+/// are free, sparing the newest one which is being written.
+pub fn purge(location: &str, camera: &str, target: u64) -> Result<Vec<Segment>, RecorderError> {
+    let dir = retention::segments_dir(location, camera)?;
+    let mut segments = retention::find_segments(location, camera)?;
+    segments.pop();
+
+    let mut deleted = Vec::new();
+    for segment in segments {
+        if free_space(&dir)? >= target {
+            break;
+        }
+        match fs::remove_file(&segment.path) {
+            Ok(()) => deleted.push(segment),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(RecorderError::Storage(e)),
+        }
+    }
+
+    Ok(deleted)
+}
@@ -0,0 +1,171 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::error::InvalidSetting;
+
+/// When and how often a failed pipeline gets rebuilt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestartPolicy {
+    /// Restart after errors instead of stopping.
+    pub enabled: bool,
+    /// Delay before the first restart, doubled for every further one.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Most restarts allowed within `window` before giving up.
+    pub max_restarts: u32,
+    pub window: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy {
+            enabled: false,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            max_restarts: 10,
+            window: Duration::from_secs(600),
+        }
+    }
+}
+
+impl RestartPolicy {
+    pub fn validate(&self) -> Result<(), InvalidSetting> {
+        if self.initial_backoff.as_millis() == 0 {
+            return Err(InvalidSetting::new("restart.initial_backoff", "must be greater than 0"));
+        }
+        if self.max_backoff < self.initial_backoff {
+            return Err(InvalidSetting::new(
+                "restart.max_backoff",
+                "must not be less than restart.initial_backoff",
+            ));
+        }
+        if self.max_restarts == 0 {
+            return Err(InvalidSetting::new("restart.max_restarts", "must be greater than 0"));
+        }
+        if self.window.as_millis() == 0 {
+            return Err(InvalidSetting::new("restart.window", "must be greater than 0"));
+        }
+        Ok(())
+    }
+}
+
+/// Decides on restarts following a [`RestartPolicy`].
+#[derive(Debug)]
+pub struct Supervisor {
+    policy: RestartPolicy,
+    restarts: VecDeque<Instant>,
+    backoff: Duration,
+    attempt: u32,
+}
+
+impl Supervisor {
+    pub fn new(policy: RestartPolicy) -> Supervisor {
+        Supervisor {
+            backoff: policy.initial_backoff,
+            policy,
+            restarts: VecDeque::new(),
+            attempt: 0,
+        }
+    }
+
+    /// Called when the pipeline failed after running for `ran_for`, returns
+    /// the delay before restarting it or `None` to give up.
+    ///
+    /// A pipeline that ran longer than the maximum backoff counts as having
+    /// recovered, the backoff starts over then.
+    pub fn next_restart(&mut self, ran_for: Duration) -> Option<Duration> {
+        if !self.policy.enabled {
+            return None;
+        }
+
+        let now = Instant::now();
+        while self.restarts.front().map(|t| now.duration_since(*t) > self.policy.window).unwrap_or(false) {
+            self.restarts.pop_front();
+        }
+        if self.restarts.len() >= self.policy.max_restarts as usize {
+            return None;
+        }
+
+        if ran_for > self.policy.max_backoff {
+            self.backoff = self.policy.initial_backoff;
+            self.attempt = 0;
+        }
+
+        let delay = self.backoff;
+        self.backoff = (self.backoff * 2).min(self.policy.max_backoff);
+        self.attempt += 1;
+        self.restarts.push_back(now);
+
+        Some(delay)
+    }
+
+    /// Number of the latest restart since the pipeline last recovered.
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> RestartPolicy {
+        RestartPolicy {
+            enabled: true,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+            max_restarts: 10,
+            window: Duration::from_secs(600),
+        }
+    }
+
+    #[test]
+    fn doubles_the_backoff_up_to_the_maximum() {
+        let mut supervisor = Supervisor::new(policy());
+        let delays = (0..5)
+            .map(|_| supervisor.next_restart(Duration::from_secs(0)).unwrap().as_secs())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, vec![1, 2, 4, 5, 5]);
+        assert_eq!(supervisor.attempt(), 5);
+    }
+
+    #[test]
+    fn starts_over_after_running_longer_than_the_maximum_backoff() {
+        let mut supervisor = Supervisor::new(policy());
+        supervisor.next_restart(Duration::from_secs(0));
+        supervisor.next_restart(Duration::from_secs(0));
+
+        assert_eq!(supervisor.next_restart(Duration::from_secs(6)), Some(Duration::from_secs(1)));
+        assert_eq!(supervisor.attempt(), 1);
+        assert_eq!(supervisor.next_restart(Duration::from_secs(0)), Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn gives_up_after_max_restarts_within_the_window() {
+        let mut supervisor = Supervisor::new(RestartPolicy { max_restarts: 3, ..policy() });
+        for _ in 0..3 {
+            assert!(supervisor.next_restart(Duration::from_secs(60)).is_some());
+        }
+        assert_eq!(supervisor.next_restart(Duration::from_secs(60)), None);
+    }
+
+    #[test]
+    fn forgets_restarts_outside_the_window() {
+        let mut supervisor = Supervisor::new(RestartPolicy {
+            max_restarts: 1,
+            window: Duration::from_millis(10),
+            ..policy()
+        });
+        assert!(supervisor.next_restart(Duration::from_secs(0)).is_some());
+        assert_eq!(supervisor.next_restart(Duration::from_secs(0)), None);
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(supervisor.next_restart(Duration::from_secs(0)).is_some());
+    }
+
+    #[test]
+    fn never_restarts_when_disabled() {
+        let mut supervisor = Supervisor::new(RestartPolicy { enabled: false, ..policy() });
+        assert_eq!(supervisor.next_restart(Duration::from_secs(0)), None);
+    }
+}
@@ -0,0 +1,34 @@
+//! Helpers shared by the unit tests.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use crate::error::InvalidSetting;
+use crate::http::{self, Handler};
+
+/// An empty directory for the test `name`, removed first if a previous run
+/// left it behind.
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("gst-camera-rs-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// The setting `result` was rejected for, failing the test when it was
+/// accepted.
+pub(crate) fn rejected<T>(result: Result<T, InvalidSetting>) -> InvalidSetting {
+    match result {
+        Ok(_) => panic!("the setting was accepted"),
+        Err(err) => err,
+    }
+}
+
+/// Has `handler` answer the raw HTTP `request`, returns the raw response.
+pub(crate) fn exchange(handler: &Handler, request: &str) -> String {
+    let mut response = Vec::new();
+    http::handle(request.as_bytes(), &mut response, handler);
+    String::from_utf8(response).unwrap()
+}
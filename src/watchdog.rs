@@ -0,0 +1,105 @@
+use gstreamer as gst;
+use gst::prelude::*;
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::error::InvalidSetting;
+
+/// Notices sources that keep playing but stop delivering frames.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchdogConfig {
+    pub enabled: bool,
+    /// How long the source may send nothing before it counts as stalled.
+    pub timeout: Duration,
+    /// Rebuild the pipeline on a stall, following the restart policy.
+    pub restart: bool,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        WatchdogConfig {
+            enabled: false,
+            timeout: Duration::from_secs(5),
+            restart: false,
+        }
+    }
+}
+
+impl WatchdogConfig {
+    /// `can_restart` tells whether the restart policy is enabled.
+    pub fn validate(&self, can_restart: bool) -> Result<(), InvalidSetting> {
+        if self.timeout.as_millis() == 0 {
+            return Err(InvalidSetting::new("watchdog.timeout", "must be greater than 0"));
+        }
+        if self.enabled && self.restart && !can_restart {
+            return Err(InvalidSetting::new("watchdog.restart", "needs restart.enabled"));
+        }
+        Ok(())
+    }
+
+    /// How often the source is checked for buffers.
+    pub fn check_interval(&self) -> Duration {
+        check_interval(self.timeout)
+    }
+}
+
+/// How often a [`BufferTracker`] gets checked to notice `timeout` passing
+/// without buffers in time.
+pub fn check_interval(timeout: Duration) -> Duration {
+    (timeout / 2).max(Duration::from_millis(100))
+}
+
+/// Time of the last buffer that went through a pad.
+#[derive(Debug, Clone)]
+pub struct BufferTracker(Arc<Mutex<Instant>>);
+
+impl BufferTracker {
+    /// Starts tracking `pad`, counting from now.
+    pub fn attach(pad: &gst::Pad) -> BufferTracker {
+        let tracker = BufferTracker(Arc::new(Mutex::new(Instant::now())));
+        let last_buffer = tracker.0.clone();
+        pad.add_probe(gst::PadProbeType::BUFFER | gst::PadProbeType::BUFFER_LIST, move |_, _| {
+            *last_buffer.lock().unwrap() = Instant::now();
+            gst::PadProbeReturn::Ok
+        });
+        tracker
+    }
+
+    /// Time since the last buffer, or since tracking started.
+    pub fn elapsed(&self) -> Duration {
+        self.0.lock().unwrap().elapsed()
+    }
+}
+
+/// Watches the output of the source of a running pipeline.
+pub struct Watchdog {
+    tracker: BufferTracker,
+    timeout: Duration,
+    stalled: bool,
+}
+
+impl Watchdog {
+    /// Starts watching the `source` bin of `pipeline`.
+    pub fn attach(pipeline: &gst::Pipeline, timeout: Duration) -> Option<Watchdog> {
+        let pad = pipeline.get_by_name("source")?.get_static_pad("src")?;
+        Some(Watchdog {
+            tracker: BufferTracker::attach(&pad),
+            timeout,
+            stalled: false,
+        })
+    }
+
+    /// Returns `Some(elapsed)` when the source just stalled, with the time
+    /// since its last buffer, and `Some(None)` when it just recovered.
+    pub fn check(&mut self) -> Option<Option<Duration>> {
+        let elapsed = self.tracker.elapsed();
+        let stalled = elapsed > self.timeout;
+        if stalled == self.stalled {
+            return None;
+        }
+
+        self.stalled = stalled;
+        Some(if stalled { Some(elapsed) } else { None })
+    }
+}